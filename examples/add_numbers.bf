@@ -19,4 +19,4 @@ We use a loop to compute 48 = 6 * 8
 < +++ +++  Add 6 to c0
 > -        Subtract 1 from c1
 ]
-< .        Print out c0 which has the value 55 which translates to "7"!
+< .        Print out c0 which has the value 55 which translates to "7"
@@ -1,7 +1,7 @@
 [ 
   Source: https://en.wikipedia.org/wiki/Brainfuck
 
-  This program prints "Hello World!" and a newline to the screen, its
+  This program prints "Hello World", an exclamation mark and a newline to the screen, its
   length is 106 active command characters. [It is not the shortest.]
 
   This loop is an "initial comment loop", a simple way of adding a comment
@@ -0,0 +1,227 @@
+//! A compact binary format for compiled programs, so that they can be saved and run again
+//! without compiling the code each time.
+//!
+//! A file starts with the magic bytes `BFC` and a version byte, followed by the number of Ops as a
+//! little endian `u32`. Each Op is then a byte identifying its kind, any values it holds as little
+//! endian `u32` or `i32`, and the line and column of the code it came from as `u32`s.
+
+use alloc::vec::Vec;
+
+use crate::{BfError, Op, Program, SourcePosition};
+
+/// The bytes every bytecode file starts with.
+const MAGIC: &[u8] = b"BFC";
+
+/// The version of the format written by `Program::to_bytecode`. Files with any other version are
+/// rejected, since the encoding of Ops may have changed.
+pub const BYTECODE_VERSION: u8 = 3;
+
+impl Program {
+    /// Encode the program as bytecode, with Ops already optimized and jump targets resolved.
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 5 + self.ops.len() * 9);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(BYTECODE_VERSION);
+        bytes.extend_from_slice(&(self.ops.len() as u32).to_le_bytes());
+
+        for (op, position) in self.ops.iter().zip(&self.positions) {
+            let (kind, values): (u8, &[u32]) = match *op {
+                Op::IncrementDp => (0, &[]),
+                Op::DecrementDp => (1, &[]),
+                Op::IncrementDpValue => (2, &[]),
+                Op::DecrementDpValue => (3, &[]),
+                Op::OutputDp => (4, &[]),
+                Op::InputDp => (5, &[]),
+                Op::JumpForward(target) => (6, &[target as u32]),
+                Op::JumpBackward(target) => (7, &[target as u32]),
+                Op::AddValue(count) => (8, &[count as u32]),
+                Op::MovePointer(count) => (9, &[count as u32]),
+                Op::SetZero => (10, &[]),
+                Op::Breakpoint => (11, &[]),
+                Op::MoveAdd { offset, factor } => (12, &[offset as u32, factor as u32]),
+                Op::ScanZero { step } => (13, &[step as u32]),
+                Op::OutputValue(value) => (14, &[value as u32]),
+                Op::AssertNonZero => (15, &[]),
+                Op::AssertZero => (16, &[]),
+            };
+
+            bytes.push(kind);
+            // signed values are written as their two's complement bits
+            for value in values {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&(position.line as u32).to_le_bytes());
+            bytes.extend_from_slice(&(position.column as u32).to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Decode a program from bytecode written by `to_bytecode`.
+    /// Returns an error if the bytecode is from a different version of the format, is cut short,
+    /// or holds jumps that don't match up.
+    pub fn from_bytecode(bytes: &[u8]) -> Result<Program, BfError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(BfError::InvalidBytecode("not a bytecode file"));
+        }
+        let version = reader.take(1)?[0];
+        if version != BYTECODE_VERSION {
+            return Err(BfError::UnsupportedBytecodeVersion { version });
+        }
+
+        let len = reader.u32()? as usize;
+        let mut program = Program {
+            ops: Vec::new(),
+            positions: Vec::new(),
+        };
+        for _ in 0..len {
+            let op = match reader.take(1)?[0] {
+                0 => Op::IncrementDp,
+                1 => Op::DecrementDp,
+                2 => Op::IncrementDpValue,
+                3 => Op::DecrementDpValue,
+                4 => Op::OutputDp,
+                5 => Op::InputDp,
+                6 => Op::JumpForward(reader.u32()? as usize),
+                7 => Op::JumpBackward(reader.u32()? as usize),
+                8 => Op::AddValue(reader.i32()?),
+                9 => Op::MovePointer(reader.i32()?),
+                10 => Op::SetZero,
+                11 => Op::Breakpoint,
+                12 => Op::MoveAdd { offset: reader.i32()?, factor: reader.i32()? },
+                13 => Op::ScanZero { step: reader.i32()? },
+                14 => Op::OutputValue(reader.i32()?),
+                15 => Op::AssertNonZero,
+                16 => Op::AssertZero,
+                _ => return Err(BfError::InvalidBytecode("unknown instruction")),
+            };
+            let line = reader.u32()? as usize;
+            let column = reader.u32()? as usize;
+
+            program.ops.push(op);
+            program.positions.push(SourcePosition { line, column });
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(BfError::InvalidBytecode("unexpected bytes after the last instruction"));
+        }
+
+        // every jump has to point at a jump that points back at it, and the loops they make have
+        // to nest inside each other rather than overlap, as the interpreter relies on both
+        let mut open = Vec::new();
+        for (index, op) in program.ops.iter().enumerate() {
+            match *op {
+                Op::JumpForward(end) => {
+                    if !matches!(program.ops.get(end), Some(&Op::JumpBackward(start)) if start == index && end > index) {
+                        return Err(BfError::InvalidBytecode("mismatched jump targets"));
+                    }
+                    open.push(index);
+                }
+                // the `[` it jumps to has been checked to jump back to it
+                Op::JumpBackward(start) if open.pop() != Some(start) => {
+                    return Err(BfError::InvalidBytecode("loops that overlap rather than nest"));
+                }
+                _ => (),
+            }
+        }
+        if !open.is_empty() {
+            return Err(BfError::InvalidBytecode("mismatched jump targets"));
+        }
+
+        Ok(program)
+    }
+}
+
+/// Reads values from the front of a slice of bytecode.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BfError> {
+        if self.bytes.len() < len {
+            return Err(BfError::InvalidBytecode("the file ends part way through"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, BfError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, BfError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use crate::{compile, compile_with, CompileOptions, Interpreter};
+
+    fn run(program: &Program, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.run(program, &mut &input[..], &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn round_trip_runs_the_same() {
+        let program = compile(",[->+>+<<]>>[-<<+>>]<.<.[>]+++[>.<-]").unwrap();
+        let loaded = Program::from_bytecode(&program.to_bytecode()).unwrap();
+        assert_eq!(loaded.ops(), program.ops());
+        assert_eq!(loaded.positions(), program.positions());
+        assert_eq!(run(&loaded, b"A"), run(&program, b"A"));
+    }
+
+    #[test]
+    fn round_trip_keeps_assertions() {
+        let options = CompileOptions { test_ops: true, ..CompileOptions::default() };
+        let program = compile_with("+?-=", &options).unwrap();
+        let loaded = Program::from_bytecode(&program.to_bytecode()).unwrap();
+        assert_eq!(loaded.ops(), [Op::AddValue(1), Op::AssertNonZero, Op::AddValue(-1), Op::AssertZero]);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = compile("+.").unwrap().to_bytecode();
+        bytes[MAGIC.len()] = BYTECODE_VERSION + 1;
+        assert!(matches!(
+            Program::from_bytecode(&bytes),
+            Err(BfError::UnsupportedBytecodeVersion { version }) if version == BYTECODE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_bytecode() {
+        let bytes = compile("+.").unwrap().to_bytecode();
+        assert!(matches!(Program::from_bytecode(&bytes[..bytes.len() - 1]), Err(BfError::InvalidBytecode(_))));
+        assert!(matches!(Program::from_bytecode(b"BF"), Err(BfError::InvalidBytecode(_))));
+    }
+
+    fn program(ops: Vec<Op>) -> Program {
+        let positions = vec![SourcePosition { line: 1, column: 1 }; ops.len()];
+        Program { ops, positions }
+    }
+
+    #[test]
+    fn rejects_mismatched_jumps() {
+        let bytes = program(vec![Op::JumpForward(1), Op::JumpBackward(1)]).to_bytecode();
+        assert!(matches!(Program::from_bytecode(&bytes), Err(BfError::InvalidBytecode(_))));
+    }
+
+    #[test]
+    fn rejects_overlapping_loops() {
+        // each pair points at each other, but the loops cross rather than nest
+        let crossing = program(vec![Op::JumpForward(2), Op::JumpForward(3), Op::JumpBackward(0), Op::JumpBackward(1)]);
+        assert!(matches!(
+            Program::from_bytecode(&crossing.to_bytecode()),
+            Err(BfError::InvalidBytecode("loops that overlap rather than nest"))
+        ));
+    }
+}
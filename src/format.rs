@@ -0,0 +1,206 @@
+//! Source to source tools that rewrite brainfuck code rather than compiling it.
+
+use alloc::string::{String, ToString};
+
+use crate::ir::{self, Instr};
+use crate::{BfError, CompileOptions, Op};
+use crate::{ASSERT_NON_ZERO, ASSERT_ZERO, BREAKPOINT, DECREMENT_DP, DECREMENT_DP_VALUE, INCREMENT_DP, INCREMENT_DP_VALUE, INPUT_DP, JUMP_BACK,
+    JUMP_FORWARD, OUTPUT_DP};
+
+/// The number of spaces each loop body is indented by.
+const INDENT: usize = 4;
+
+/// Pretty print brainfuck code, with the commands between loops on one line, each `[` and `]` on a
+/// line of its own, and each loop body indented one level further than its loop. Comments are
+/// removed, and `#` is only kept if it is a breakpoint in `options`.
+/// Returns an error if the code couldn't be compiled with `options`.
+pub fn format_code(code: impl AsRef<[u8]>, options: &CompileOptions) -> Result<String, BfError> {
+    let instrs = ir::parse(code, options)?;
+    let mut formatted = String::new();
+    format_block(&mut formatted, &instrs, 0);
+    Ok(formatted)
+}
+
+/// Strip brainfuck code down to only its commands, on a single line. Comments are removed, and
+/// `#` is only kept if it is a breakpoint in `options`. If `fold` is true, commands that cancel
+/// each other out, like `+-` and `<>`, are removed as well, including any pairs left next to each
+/// other once a pair between them has been removed.
+/// Returns an error if the code couldn't be compiled with `options`.
+pub fn minify_code(code: impl AsRef<[u8]>, options: &CompileOptions, fold: bool) -> Result<String, BfError> {
+    let instrs = ir::parse(code, options)?;
+    let mut minified = String::new();
+    minify_block(&mut minified, &instrs, fold);
+    minified.push('\n');
+    Ok(minified)
+}
+
+fn minify_block(minified: &mut String, instrs: &[Instr], fold: bool) {
+    for instr in instrs {
+        match instr {
+            Instr::Op(op, _) => {
+                let command = command(*op);
+                // the last command can only be an inverse if it is in the same block, since the
+                // block's `[` is between it and anything before
+                if fold && inverse(command).is_some_and(|inverse| minified.ends_with(inverse)) {
+                    minified.pop();
+                } else {
+                    minified.push(command);
+                }
+            }
+            Instr::Loop { body, .. } => {
+                minified.push(JUMP_FORWARD);
+                minify_block(minified, body, fold);
+                minified.push(JUMP_BACK);
+            }
+        }
+    }
+}
+
+/// The command that undoes `command`, if there is one.
+fn inverse(command: char) -> Option<char> {
+    match command {
+        INCREMENT_DP => Some(DECREMENT_DP),
+        DECREMENT_DP => Some(INCREMENT_DP),
+        INCREMENT_DP_VALUE => Some(DECREMENT_DP_VALUE),
+        DECREMENT_DP_VALUE => Some(INCREMENT_DP_VALUE),
+        _ => None,
+    }
+}
+
+fn format_block(formatted: &mut String, instrs: &[Instr], depth: usize) {
+    let mut line = String::new();
+    for instr in instrs {
+        match instr {
+            Instr::Op(op, _) => line.push(command(*op)),
+            Instr::Loop { body, .. } => {
+                if !line.is_empty() {
+                    push_line(formatted, &line, depth);
+                    line.clear();
+                }
+                push_line(formatted, &JUMP_FORWARD.to_string(), depth);
+                format_block(formatted, body, depth + 1);
+                push_line(formatted, &JUMP_BACK.to_string(), depth);
+            }
+        }
+    }
+
+    if !line.is_empty() {
+        push_line(formatted, &line, depth);
+    }
+}
+
+fn push_line(formatted: &mut String, line: &str, depth: usize) {
+    formatted.extend(core::iter::repeat_n(' ', depth * INDENT));
+    formatted.push_str(line);
+    formatted.push('\n');
+}
+
+/// Write compiled Ops back out as brainfuck code, with each combined Op expanded into the commands
+/// it does the work of, such as `AddValue(3)` into `+++` and `SetZero` into `[-]`. Compiling the
+/// code again gives a program that does the same thing, although without any comments, and loops
+/// that were optimized away come back as simpler loops.
+/// `MoveAdd` Ops are expected to be followed by a `SetZero`, as they are when compiled, and
+/// together they become a single loop.
+pub fn decompile(ops: &[Op]) -> String {
+    let mut code = String::new();
+    // within the loop of a run of `MoveAdd`s, how far the pointer is from the cell counting down
+    let mut move_add_offset = None;
+    for &op in ops {
+        match op {
+            Op::JumpForward(_) => code.push(JUMP_FORWARD),
+            Op::JumpBackward(_) => code.push(JUMP_BACK),
+            Op::AddValue(count) => push_count(&mut code, count, INCREMENT_DP_VALUE, DECREMENT_DP_VALUE),
+            Op::MovePointer(count) => push_count(&mut code, count, INCREMENT_DP, DECREMENT_DP),
+            Op::MoveAdd { offset, factor } => {
+                // the loop counts the cell down to 0, adding to each other cell on the way
+                let current = *move_add_offset.get_or_insert_with(|| {
+                    code.push(JUMP_FORWARD);
+                    code.push(DECREMENT_DP_VALUE);
+                    0
+                });
+                push_count(&mut code, offset - current, INCREMENT_DP, DECREMENT_DP);
+                push_count(&mut code, factor, INCREMENT_DP_VALUE, DECREMENT_DP_VALUE);
+                move_add_offset = Some(offset);
+            }
+            // the loop of the `MoveAdd`s before it already leaves the cell at 0
+            Op::SetZero if move_add_offset.is_some() => {
+                push_count(&mut code, -move_add_offset.take().unwrap_or_default(), INCREMENT_DP, DECREMENT_DP);
+                code.push(JUMP_BACK);
+            }
+            Op::SetZero => {
+                code.push(JUMP_FORWARD);
+                code.push(DECREMENT_DP_VALUE);
+                code.push(JUMP_BACK);
+            }
+            Op::ScanZero { step } => {
+                code.push(JUMP_FORWARD);
+                push_count(&mut code, step, INCREMENT_DP, DECREMENT_DP);
+                code.push(JUMP_BACK);
+            }
+            // the cell already holds the value written
+            Op::OutputValue(_) => code.push(OUTPUT_DP),
+            _ => code.push(command(op)),
+        }
+    }
+    code
+}
+
+/// Push `count` of `up`, or if `count` is negative, as many of `down`.
+fn push_count(code: &mut String, count: i32, up: char, down: char) {
+    let command = if count < 0 { down } else { up };
+    code.extend(core::iter::repeat_n(command, count.unsigned_abs() as usize));
+}
+
+/// The command an Op was parsed from. Only the Ops produced by `ir::parse` are expected, since
+/// combined Ops can't be written back as a single command.
+fn command(op: Op) -> char {
+    match op {
+        Op::IncrementDp => INCREMENT_DP,
+        Op::DecrementDp => DECREMENT_DP,
+        Op::IncrementDpValue => INCREMENT_DP_VALUE,
+        Op::DecrementDpValue => DECREMENT_DP_VALUE,
+        Op::OutputDp => OUTPUT_DP,
+        Op::InputDp => INPUT_DP,
+        Op::Breakpoint => BREAKPOINT,
+        Op::AssertNonZero => ASSERT_NON_ZERO,
+        Op::AssertZero => ASSERT_ZERO,
+        _ => unreachable!("{} isn't parsed from a single command", op.name()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    #[test]
+    fn format_nested_loops() {
+        let code = "+[>[>+<-] comment <-]\n.";
+        let formatted = format_code(code, &CompileOptions::default()).unwrap();
+        assert_eq!(formatted, "+\n[\n    >\n    [\n        >+<-\n    ]\n    <-\n]\n.\n");
+        assert_eq!(compile(&formatted).unwrap().ops(), compile(code).unwrap().ops());
+    }
+
+    #[test]
+    fn minify_keeps_only_commands() {
+        let code = "This adds two and three:\n  ++ > +++ [ - < + > ] < .  (prints 5)";
+        let minified = minify_code(code, &CompileOptions::default(), false).unwrap();
+        assert_eq!(minified, "++>+++[-<+>]<.\n");
+    }
+
+    #[test]
+    fn minify_aggressive_folds_cancelling_commands() {
+        let minified = minify_code("+<>-+ x [-+]>-+<", &CompileOptions::default(), true).unwrap();
+        assert_eq!(minified, "+[]\n");
+    }
+
+    #[test]
+    fn decompile_round_trip() {
+        for code in ["[->+<]", "+++[>+<-]>[-]<<.", "[>>]+[<]--,"] {
+            let program = compile(code).unwrap();
+            let decompiled = decompile(program.ops());
+            assert_eq!(compile(&decompiled).unwrap().ops(), program.ops(), "{code} became {decompiled}");
+        }
+        assert_eq!(decompile(&[Op::AddValue(3), Op::SetZero, Op::MovePointer(-2)]), "+++[-]<<");
+    }
+}
@@ -0,0 +1,78 @@
+//! The control flow graph of a compiled program, written in the DOT language of Graphviz so that it
+//! can be drawn with `dot -Tpng`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::{Op, Program};
+
+/// Write the control flow graph of `program` in DOT. Each node is a basic block, a run of Ops that
+/// always execute one after the other, listing its Ops by index. A block ends at each jump, with
+/// edges labelled `enter` and `skip` from a `[`, and `loop` and `exit` from a `]`. Running off the
+/// end of the program leads to a node named `end`, and the program starts at a node named `start`.
+pub fn control_flow_dot(program: &Program) -> String {
+    let ops = program.ops();
+
+    // a block starts at the beginning and after each jump, which is also where every jump goes to
+    let mut starts: Vec<usize> = (0..ops.len())
+        .filter(|&index| index == 0 || matches!(ops[index - 1], Op::JumpForward(_) | Op::JumpBackward(_)))
+        .collect();
+    starts.push(ops.len());
+
+    let node = |index: usize| if index == ops.len() { String::from("end") } else { format!("b{index}") };
+
+    let mut dot = String::new();
+    dot.push_str("digraph program {\n");
+    dot.push_str("    node [shape=box, fontname=monospace];\n");
+    dot.push_str("    start [shape=point];\n");
+    dot.push_str("    end [shape=doublecircle];\n");
+    let _ = writeln!(dot, "    start -> {};", node(0));
+
+    for block in starts.windows(2) {
+        let (start, end) = (block[0], block[1]);
+        let mut label = String::new();
+        for (index, op) in ops.iter().enumerate().take(end).skip(start) {
+            // `\l` ends a line and aligns it to the left
+            let _ = write!(label, "{index:04} {op}\\l");
+        }
+        let _ = writeln!(dot, "    {} [label=\"{label}\"];", node(start));
+
+        let from = node(start);
+        match ops[end - 1] {
+            Op::JumpForward(loop_end) => {
+                let _ = writeln!(dot, "    {from} -> {} [label=\"enter\"];", node(end));
+                let _ = writeln!(dot, "    {from} -> {} [label=\"skip\"];", node(loop_end + 1));
+            }
+            Op::JumpBackward(loop_start) => {
+                let _ = writeln!(dot, "    {from} -> {} [label=\"loop\"];", node(loop_start + 1));
+                let _ = writeln!(dot, "    {from} -> {} [label=\"exit\"];", node(end));
+            }
+            _ => {
+                let _ = writeln!(dot, "    {from} -> {};", node(end));
+            }
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    #[test]
+    fn loop_has_a_back_edge() {
+        let dot = control_flow_dot(&compile("+[-.]").unwrap());
+        assert!(dot.starts_with("digraph program {\n"));
+        // the block ending in `]` goes back to the start of the body, or on past the loop
+        assert!(dot.contains("b2 [label=\"0002 AddValue -1\\l0003 OutputDp\\l0004 JumpBackward -> 1\\l\"];\n"));
+        assert!(dot.contains("    b2 -> b2 [label=\"loop\"];\n"));
+        assert!(dot.contains("    b2 -> end [label=\"exit\"];\n"));
+        assert!(dot.contains("    b0 -> b2 [label=\"enter\"];\n"));
+        assert!(dot.contains("    b0 -> end [label=\"skip\"];\n"));
+    }
+}
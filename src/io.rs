@@ -0,0 +1,89 @@
+//! The input read by `,` and the output written by `.`, as traits so that the interpreter doesn't
+//! need `std`. With the `std` feature they are implemented for every `std::io::Read` and
+//! `std::io::Write`, and without it they can be implemented against whatever I/O is available,
+//! such as a UART.
+
+/// The error from reading input or writing output, which is `std::io::Error` with the `std`
+/// feature.
+#[cfg(feature = "std")]
+pub type IoError = std::io::Error;
+
+/// The error from reading input or writing output.
+#[cfg(not(feature = "std"))]
+#[derive(Debug)]
+pub struct IoError;
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for IoError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "I/O error")
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for IoError {}
+
+/// A source of bytes for the `,` command.
+pub trait Input {
+    /// Read the next byte, or `None` once the input has run out.
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError>;
+}
+
+/// A destination for the bytes written by the `.` command.
+pub trait Output {
+    /// Write all of `bytes`.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError>;
+
+    /// Make sure everything written so far has reached its destination.
+    fn flush(&mut self) -> Result<(), IoError>;
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> Input for R {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        let mut byte = [0];
+        match self.read_exact(&mut byte) {
+            Ok(()) => Ok(Some(byte[0])),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> Output for W {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        self.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        std::io::Write::flush(self)
+    }
+}
+
+/// Without `std`, a slice of bytes can still be the input, read from the front.
+#[cfg(not(feature = "std"))]
+impl Input for &[u8] {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        match self.split_first() {
+            Some((&byte, rest)) => {
+                *self = rest;
+                Ok(Some(byte))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Without `std`, the output can still be collected in a `Vec`.
+#[cfg(not(feature = "std"))]
+impl Output for alloc::vec::Vec<u8> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
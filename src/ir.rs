@@ -0,0 +1,573 @@
+//! An intermediate representation of brainfuck code, where each loop holds its body rather than
+//! being a pair of jumps. Parsing into it matches up the brackets, optimizations rewrite whole
+//! loops at a time, and it is then lowered into the flat list of Ops of a `Program`.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::slice;
+
+use crate::{BfError, CompileOptions, Op, OptLevel, Program, SourcePosition};
+use crate::{ASSERT_NON_ZERO, ASSERT_ZERO, BREAKPOINT, DECREMENT_DP, DECREMENT_DP_VALUE, INCREMENT_DP, INCREMENT_DP_VALUE, INPUT_DP, JUMP_BACK,
+    JUMP_FORWARD, OUTPUT_DP};
+
+/// The most times a loop can run and still be unrolled at `OptLevel::O3`.
+const MAX_UNROLL: i32 = 8;
+/// The most Ops an unrolled loop can become, so that unrolling doesn't grow the program too much.
+const MAX_UNROLLED_OPS: usize = 64;
+
+/// An instruction of the intermediate representation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    /// Any Op other than a jump, along with the position in the code it came from.
+    Op(Op, SourcePosition),
+    /// A loop, along with the positions of its `[` and `]`.
+    Loop {
+        body: Vec<Instr>,
+        start: SourcePosition,
+        end: SourcePosition,
+    },
+}
+
+/// How many times each optimization was applied while compiling a program, and how many Ops the
+/// program had before and after, as given by `compile_with_report`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OptReport {
+    /// Runs of two or more `+` and `-`, or `>` and `<`, combined into a single Op.
+    pub coalesced_runs: usize,
+    /// Loops like `[-]` replaced with `SetZero`.
+    pub clear_loops: usize,
+    /// Loops like `[>]` replaced with `ScanZero`.
+    pub scan_loops: usize,
+    /// Loops like `[->+<]` replaced with `MoveAdd`s.
+    pub copy_loops: usize,
+    /// `.`s of a cell whose value is known replaced with `OutputValue`.
+    pub folded_outputs: usize,
+    /// Loops unrolled into copies of their body.
+    pub unrolled_loops: usize,
+    /// The number of Ops the program would have without any optimization.
+    pub ops_before: usize,
+    /// The number of Ops the optimized program has.
+    pub ops_after: usize,
+}
+
+/// Shows the report on one line, such as
+/// `coalesced 1 runs, replaced 1 clear loops, 0 scan loops and 0 copy loops, folded 0 outputs,
+/// unrolled 0 loops, reduced the op count from 6 to 2`.
+impl fmt::Display for OptReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "coalesced {} runs, replaced {} clear loops, {} scan loops and {} copy loops, folded {} outputs, \
+            unrolled {} loops, reduced the op count from {} to {}",
+            self.coalesced_runs,
+            self.clear_loops,
+            self.scan_loops,
+            self.copy_loops,
+            self.folded_outputs,
+            self.unrolled_loops,
+            self.ops_before,
+            self.ops_after
+        )
+    }
+}
+
+/// Parse brainfuck code into a list of instructions, without optimizing it.
+/// Returns an error if the code contains mismatched jump instructions, unless they are closed or
+/// ignored with `auto_close`, or loops nested more deeply than the limit, or in strict mode if it
+/// contains a character that isn't a command or whitespace.
+pub fn parse(code: impl AsRef<[u8]>, options: &CompileOptions) -> Result<Vec<Instr>, BfError> {
+    let mut parser = Parser {
+        bytes: code.as_ref().iter(),
+        position: SourcePosition { line: 1, column: 1 },
+        depth: 0,
+        options,
+    };
+
+    match parser.block()? {
+        (instrs, None) => Ok(instrs),
+        (_, Some(position)) => Err(BfError::UnmatchedJump { jump: JUMP_BACK, position }),
+    }
+}
+
+struct Parser<'a> {
+    bytes: slice::Iter<'a, u8>,
+    position: SourcePosition,
+    /// The number of loops the instructions being parsed are inside.
+    depth: usize,
+    options: &'a CompileOptions,
+}
+
+impl Parser<'_> {
+    /// Parse instructions up to the next `]` or the end of the code. The position of the `]` is
+    /// also returned, or `None` if the end of the code was reached.
+    fn block(&mut self) -> Result<(Vec<Instr>, Option<SourcePosition>), BfError> {
+        let mut instrs = Vec::new();
+
+        while let Some(&byte) = self.bytes.next() {
+            let position = self.position;
+            self.position.advance(byte);
+
+            let op = match self.options.commands.command(byte) {
+                Some(INCREMENT_DP) =>       Op::IncrementDp,
+                Some(DECREMENT_DP) =>       Op::DecrementDp,
+                Some(INCREMENT_DP_VALUE) => Op::IncrementDpValue,
+                Some(DECREMENT_DP_VALUE) => Op::DecrementDpValue,
+                Some(OUTPUT_DP) =>          Op::OutputDp,
+                Some(INPUT_DP) =>           Op::InputDp,
+                Some(JUMP_FORWARD) => {
+                    if let Some(limit) = self.options.max_depth.filter(|&limit| self.depth >= limit) {
+                        return Err(BfError::NestingTooDeep { limit, position });
+                    }
+                    self.depth += 1;
+                    let (body, end) = match self.block()? {
+                        (body, Some(end)) => (body, end),
+                        // the loop is closed at the end of the code
+                        (body, None) if self.options.auto_close => (body, self.position),
+                        (_, None) => return Err(BfError::UnmatchedJump { jump: JUMP_FORWARD, position }),
+                    };
+                    self.depth -= 1;
+                    instrs.push(Instr::Loop { body, start: position, end });
+                    continue;
+                }
+                Some(JUMP_BACK) if self.depth == 0 && self.options.auto_close => continue,
+                Some(JUMP_BACK) => return Ok((instrs, Some(position))),
+                _ if byte == BREAKPOINT as u8 && self.options.breakpoints => Op::Breakpoint,
+                _ if byte == ASSERT_NON_ZERO as u8 && self.options.test_ops => Op::AssertNonZero,
+                _ if byte == ASSERT_ZERO as u8 && self.options.test_ops => Op::AssertZero,
+                _ if self.options.strict && !matches!(byte, b' ' | b'\t' | b'\n' | b'\r') => {
+                    return Err(BfError::UnexpectedCharacter { byte, position });
+                }
+                // any other character is ignored, so that brainfuck programs can contain whitespace and comments.
+                _ => continue,
+            };
+
+            instrs.push(Instr::Op(op, position));
+        }
+
+        Ok((instrs, None))
+    }
+}
+
+/// Coalesces runs of value and pointer Ops into single counted Ops, so that `run` can apply them
+/// in one step, and replaces loops that only move the pointer or do arithmetic with fewer Ops.
+/// From `OptLevel::O2`, the value written by a `.` is also worked out when compiling if it can be,
+/// and at `OptLevel::O3`, small loops that run a known number of times are unrolled. Which of these
+/// are done depends on `level`, and nothing is done at `OptLevel::O0`.
+/// A combined Op keeps the source position of the first Op it was made from, and the Ops replacing
+/// a loop all come from its `[`.
+pub fn optimize(instrs: Vec<Instr>, level: OptLevel) -> Vec<Instr> {
+    optimize_with_report(instrs, level, &mut OptReport::default())
+}
+
+/// Optimize the instructions like `optimize`, adding each optimization applied to `report`.
+pub fn optimize_with_report(instrs: Vec<Instr>, level: OptLevel, report: &mut OptReport) -> Vec<Instr> {
+    if level == OptLevel::O0 {
+        return instrs;
+    }
+    let mut optimized = Vec::<Instr>::with_capacity(instrs.len());
+    // the number of commands in the run the last Op was made from since it was pushed, so that
+    // each run is only counted once it has a second command
+    let mut run = 0;
+
+    for instr in instrs {
+        let (op, position) = match instr {
+            Instr::Op(op, position) => (op, position),
+            Instr::Loop { body, start, end } => {
+                let body = optimize_with_report(body, level, report);
+                if let Some(ops) = simple_loop(&body, level) {
+                    match ops[..] {
+                        [Op::SetZero] => report.clear_loops += 1,
+                        [Op::ScanZero { .. }] => report.scan_loops += 1,
+                        _ => report.copy_loops += 1,
+                    }
+                    optimized.extend(ops.into_iter().map(|op| Instr::Op(op, start)));
+                } else if let Some(count) = unroll_count(&optimized, &body).filter(|_| level == OptLevel::O3) {
+                    report.unrolled_loops += 1;
+                    for _ in 0..count {
+                        optimized.extend(body.iter().cloned());
+                    }
+                } else {
+                    optimized.push(Instr::Loop { body, start, end });
+                }
+                run = 0;
+                continue;
+            }
+        };
+
+        let (value, pointer) = match op {
+            Op::IncrementDpValue => (1, 0),
+            Op::DecrementDpValue => (-1, 0),
+            Op::IncrementDp => (0, 1),
+            Op::DecrementDp => (0, -1),
+            _ => {
+                optimized.push(Instr::Op(op, position));
+                run = 0;
+                continue;
+            }
+        };
+
+        match optimized.last_mut() {
+            Some(Instr::Op(Op::AddValue(count), _)) if value != 0 => *count += value,
+            // moves only combine in the same direction, since `<>` at cell 0 is an error that a
+            // net move of 0 would skip
+            Some(Instr::Op(Op::MovePointer(count), _)) if count.signum() == pointer => *count += pointer,
+            _ => {
+                let op = if value != 0 { Op::AddValue(value) } else { Op::MovePointer(pointer) };
+                optimized.push(Instr::Op(op, position));
+                run = 0;
+            }
+        }
+        run += 1;
+        if run == 2 {
+            report.coalesced_runs += 1;
+        }
+
+        // a run that cancels itself out, like `+-`, doesn't need to be executed at all
+        if let Some(Instr::Op(Op::AddValue(0), _)) = optimized.last() {
+            optimized.pop();
+            run = 0;
+        }
+    }
+
+    if level >= OptLevel::O2 {
+        report.folded_outputs += fold_output(&mut optimized);
+    }
+    optimized
+}
+
+/// The number of Ops the instructions become once they are lowered, with each loop becoming two
+/// jumps.
+pub(crate) fn op_count(instrs: &[Instr]) -> usize {
+    instrs.iter()
+        .map(|instr| match instr {
+            Instr::Op(..) => 1,
+            Instr::Loop { body, .. } => op_count(body) + 2,
+        })
+        .sum()
+}
+
+/// Replace each `OutputDp` of a cell whose value is already known with an `OutputValue`. The value
+/// is only known once the cell has been set to 0, as in `[-]+++.`, and only while the data pointer
+/// stays at it and nothing but adding to it changes it. Any cell could be anything once the data
+/// pointer has moved, which might have wrapped it around to the same cell, or after a loop.
+/// Returns the number of `OutputDp`s replaced.
+fn fold_output(instrs: &mut [Instr]) -> usize {
+    let mut folded = 0;
+    let mut known = None;
+    for instr in instrs {
+        let Instr::Op(op, _) = instr else {
+            known = None;
+            continue;
+        };
+        known = match *op {
+            Op::SetZero => Some(0),
+            Op::AddValue(count) => known.map(|value: i32| value.wrapping_add(count)),
+            Op::IncrementDpValue => known.map(|value| value.wrapping_add(1)),
+            Op::DecrementDpValue => known.map(|value| value.wrapping_sub(1)),
+            Op::OutputDp | Op::OutputValue(_) => {
+                if let Some(value) = known {
+                    if *op == Op::OutputDp {
+                        folded += 1;
+                    }
+                    *op = Op::OutputValue(value);
+                }
+                known
+            }
+            _ => None,
+        };
+    }
+    folded
+}
+
+/// The Ops to replace a loop with `body`, if it is a clear loop, or from `OptLevel::O2`, a scan
+/// loop or an arithmetic loop.
+fn simple_loop(body: &[Instr], level: OptLevel) -> Option<Vec<Op>> {
+    // neither kind of loop has a loop inside it
+    let ops = body.iter()
+        .map(|instr| match instr {
+            Instr::Op(op, _) => Some(*op),
+            Instr::Loop { .. } => None,
+        })
+        .collect::<Option<Vec<Op>>>()?;
+
+    match ops[..] {
+        [Op::AddValue(1 | -1)] => Some(vec![Op::SetZero]),
+        _ if level == OptLevel::O1 => None,
+        [Op::MovePointer(step)] => Some(vec![Op::ScanZero { step }]),
+        _ => arithmetic_loop(&ops),
+    }
+}
+
+/// The number of times a loop with `body` runs after `before`, if it can be unrolled into that many
+/// copies of its body. The count has to be known, so the cell has to have been set to 0 and then
+/// added to, and it can be at most `MAX_UNROLL`. The body has to count the cell down by 1 and
+/// return the data pointer to it, and can't do anything else but write bytes, since changing any
+/// other cell might change the count if the data pointer wraps around to the same cell.
+fn unroll_count(before: &[Instr], body: &[Instr]) -> Option<usize> {
+    let count = known_value(before).filter(|count| (1..=MAX_UNROLL).contains(count))? as usize;
+    if body.len() * count > MAX_UNROLLED_OPS {
+        return None;
+    }
+
+    let mut offset = 0;
+    let mut change = 0;
+    for instr in body {
+        match instr {
+            Instr::Op(Op::AddValue(amount), _) if offset == 0 => change += amount,
+            Instr::Op(Op::MovePointer(amount), _) => offset += amount,
+            Instr::Op(Op::OutputDp | Op::OutputValue(_), _) => {}
+            _ => return None,
+        }
+    }
+    (offset == 0 && change == -1).then_some(count)
+}
+
+/// The value of the cell at the data pointer after `instrs`, if it has been set to 0 and since
+/// then only added to.
+fn known_value(instrs: &[Instr]) -> Option<i32> {
+    let mut value = 0;
+    for instr in instrs.iter().rev() {
+        match instr {
+            Instr::Op(Op::AddValue(count), _) => value += count,
+            Instr::Op(Op::OutputDp | Op::OutputValue(_), _) => {}
+            Instr::Op(Op::SetZero, _) => return Some(value),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The Ops to replace a loop with `body`, if the loop only adds to cells a fixed distance from
+/// the data pointer, returns the data pointer to where it started, and counts the byte at the
+/// data pointer down or up by 1 each iteration. Each other cell changed gets a `MoveAdd`, and
+/// then the byte at the data pointer is set to 0. The data pointer can't go any further from
+/// where it started than the cells it changes, as the `MoveAdd`s wouldn't go out of bounds
+/// where the loop would.
+fn arithmetic_loop(body: &[Op]) -> Option<Vec<Op>> {
+    let mut offset = 0;
+    let (mut lowest, mut highest) = (0, 0);
+    // the amount added to each cell by one iteration, by offset from the data pointer
+    let mut changes = Vec::<(i32, i32)>::new();
+    for &op in body {
+        match op {
+            Op::AddValue(count) => match changes.iter_mut().find(|(cell, _)| *cell == offset) {
+                Some((_, total)) => *total += count,
+                None => changes.push((offset, count)),
+            },
+            Op::MovePointer(count) => {
+                offset += count;
+                (lowest, highest) = (lowest.min(offset), highest.max(offset));
+            }
+            _ => return None,
+        }
+    }
+    let changed = |cell| cell == 0 || changes.iter().any(|&(offset, total)| offset == cell && total != 0);
+    if offset != 0 || !changed(lowest) || !changed(highest) {
+        return None;
+    }
+
+    // counting down runs the loop as many times as the byte's value, and counting up runs it
+    // as many times as the value's negation
+    let direction = match changes.iter().find(|(cell, _)| *cell == 0) {
+        Some((_, -1)) => 1,
+        Some((_, 1)) => -1,
+        _ => return None,
+    };
+
+    let mut ops: Vec<Op> = changes.into_iter()
+        .filter(|&(cell, total)| cell != 0 && total != 0)
+        .map(|(offset, total)| Op::MoveAdd { offset, factor: total * direction })
+        .collect();
+    ops.push(Op::SetZero);
+    Some(ops)
+}
+
+/// Flatten the instructions into a `Program`, with each loop becoming a pair of jumps that hold
+/// the index of each other.
+pub fn lower(instrs: &[Instr]) -> Program {
+    let mut program = Program {
+        ops: Vec::new(),
+        positions: Vec::new(),
+    };
+    lower_into(&mut program, instrs);
+    program
+}
+
+fn lower_into(program: &mut Program, instrs: &[Instr]) {
+    for instr in instrs {
+        match instr {
+            Instr::Op(op, position) => {
+                program.ops.push(*op);
+                program.positions.push(*position);
+            }
+            Instr::Loop { body, start, end } => {
+                // the target of the jump forward isn't known until the body is in place
+                let start_index = program.ops.len();
+                program.ops.push(Op::JumpForward(0));
+                program.positions.push(*start);
+
+                lower_into(program, body);
+
+                let end_index = program.ops.len();
+                program.ops.push(Op::JumpBackward(start_index));
+                program.positions.push(*end);
+                program.ops[start_index] = Op::JumpForward(end_index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::ToString;
+    use crate::{compile, compile_with, compile_with_report, Interpreter, MAX_DEPTH};
+
+    const LEVELS: [OptLevel; 4] = [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3];
+
+    fn compile_at(code: &str, opt_level: OptLevel) -> Program {
+        compile_with(code, &CompileOptions { opt_level, ..CompileOptions::default() }).unwrap()
+    }
+
+    /// Run `code` at `opt_level`, returning whether it succeeded, what it wrote and the memory
+    /// cells it used.
+    fn run_at(code: &str, opt_level: OptLevel) -> (bool, Vec<u8>, Vec<u8>) {
+        let mut interpreter: Interpreter = Interpreter::with_size(8);
+        let mut output = Vec::new();
+        let result = interpreter.run(&compile_at(code, opt_level), &mut &b"a"[..], &mut output);
+        (result.is_ok(), output, interpreter.data().to_vec())
+    }
+
+    #[test]
+    fn optimizing_keeps_out_of_bounds_errors() {
+        for code in ["<>++<+<", "+-<>[+<+,>]+", "+[-<<>>>+<]", ">+[->+<<<>>]"] {
+            for level in LEVELS {
+                assert_eq!(run_at(code, level), run_at(code, OptLevel::O0), "{code} at {level:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn optimizing_keeps_output() {
+        let code = "++++++++[>++++++++<-]>+.[-]<<>>,[->+>+<<]>>.<[-<+>]<.+++[>.<-]";
+        for level in LEVELS {
+            assert_eq!(run_at(code, level), run_at(code, OptLevel::O0), "at {level:?}");
+        }
+    }
+
+    fn at(column: usize) -> SourcePosition {
+        SourcePosition { line: 1, column }
+    }
+
+    #[test]
+    fn parse_nested_loops() {
+        let instrs = parse("[[]+]", &CompileOptions::default()).unwrap();
+        assert_eq!(instrs, [Instr::Loop {
+            body: vec![
+                Instr::Loop { body: vec![], start: at(2), end: at(3) },
+                Instr::Op(Op::IncrementDpValue, at(4)),
+            ],
+            start: at(1),
+            end: at(5),
+        }]);
+    }
+
+    #[test]
+    fn parse_unbalanced_loops() {
+        let options = CompileOptions::default();
+        assert!(matches!(parse("[[]", &options), Err(BfError::UnmatchedJump { jump: '[', .. })));
+        assert!(matches!(parse("[]]", &options), Err(BfError::UnmatchedJump { jump: ']', .. })));
+    }
+
+    #[test]
+    fn opt_levels() {
+        assert_eq!(
+            compile_at("+-[-]>", OptLevel::O0).ops(),
+            [
+                Op::IncrementDpValue,
+                Op::DecrementDpValue,
+                Op::JumpForward(4),
+                Op::DecrementDpValue,
+                Op::JumpBackward(2),
+                Op::IncrementDp,
+            ]
+        );
+        assert_eq!(compile_at("[-]", OptLevel::O1).ops(), [Op::SetZero]);
+        assert_eq!(compile_at("[-]", OptLevel::O2).ops(), [Op::SetZero]);
+        assert_eq!(compile_at("[>]", OptLevel::O2).ops(), [Op::ScanZero { step: 1 }]);
+        assert_eq!(OptLevel::default(), OptLevel::O2);
+    }
+
+    #[test]
+    fn folds_output_of_known_values() {
+        let code = format!("[-]{}.+.", "+".repeat(65));
+        // the cell is still changed, in case it's used again
+        assert_eq!(
+            compile_at(&code, OptLevel::O2).ops(),
+            [Op::SetZero, Op::AddValue(65), Op::OutputValue(65), Op::AddValue(1), Op::OutputValue(66)]
+        );
+        assert_eq!(compile_at(&code, OptLevel::O1).ops()[2], Op::OutputDp);
+        for level in LEVELS {
+            assert_eq!(run_at(&code, level).1, b"AB");
+        }
+
+        // input, a move or a loop makes the value unknown
+        for code in ["[-]+,.", "[-]+><.", "[-]+[>]."] {
+            assert!(!compile_at(code, OptLevel::O2).ops().iter().any(|op| matches!(op, Op::OutputValue(_))), "{code}");
+        }
+    }
+
+    #[test]
+    fn nesting_beyond_the_limit() {
+        let options = CompileOptions { max_depth: Some(3), ..CompileOptions::default() };
+        assert!(parse("[[[+]]][]", &options).is_ok());
+        assert!(matches!(
+            parse("[[[[+]]]]", &options),
+            Err(BfError::NestingTooDeep { limit: 3, position: SourcePosition { line: 1, column: 4 } })
+        ));
+
+        // there is a limit by default, so that compiling deeply nested code errors rather than
+        // overflowing the stack
+        let nested = |depth| format!("{}+{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(compile(nested(MAX_DEPTH)).is_ok());
+        assert!(matches!(
+            compile(nested(100_000)),
+            Err(BfError::NestingTooDeep { limit: MAX_DEPTH, .. })
+        ));
+    }
+
+    #[test]
+    fn unrolls_counted_loops() {
+        let code = "[-]+++[.-]>[-]++[>.<-]";
+        let unrolled = compile_at(code, OptLevel::O3);
+        assert!(!unrolled.iter().any(|op| matches!(op, Op::JumpForward(_))));
+        assert!(unrolled.ops().len() > compile_at(code, OptLevel::O2).ops().len());
+        let rolled = run_at(code, OptLevel::O2);
+        assert_eq!((rolled.0, &rolled.1[..]), (true, &[3, 2, 1, 0, 0][..]));
+        assert_eq!(run_at(code, OptLevel::O3), rolled);
+
+        // the count isn't known without `[-]`, and the body can't change other cells
+        for code in ["+++[.-]", "[-]+++[>+.<-]", "[-]+++[,-]", "[-]+++[--]"] {
+            assert!(compile_at(code, OptLevel::O3).iter().any(|op| matches!(op, Op::JumpForward(_))), "{code}");
+        }
+    }
+
+    #[test]
+    fn opt_report_counts_each_optimization() {
+        let (program, report) = compile_with_report("[-]+++", &CompileOptions::default()).unwrap();
+        assert_eq!((report.clear_loops, report.coalesced_runs), (1, 1));
+        assert_eq!((report.scan_loops, report.copy_loops, report.folded_outputs, report.unrolled_loops), (0, 0, 0, 0));
+        assert_eq!((report.ops_before, report.ops_after), (6, program.ops().len()));
+        assert_eq!(
+            report.to_string(),
+            "coalesced 1 runs, replaced 1 clear loops, 0 scan loops and 0 copy loops, folded 0 outputs, \
+             unrolled 0 loops, reduced the op count from 6 to 2"
+        );
+
+        let options = CompileOptions { opt_level: OptLevel::O0, ..CompileOptions::default() };
+        let (_, report) = compile_with_report("[-]+++", &options).unwrap();
+        assert_eq!((report.clear_loops, report.coalesced_runs, report.ops_after), (0, 0, 6));
+    }
+}
@@ -0,0 +1,2777 @@
+//! An implementation of brainfuck, which can be embedded through `execute` or by driving an
+//! `Interpreter` directly.
+//!
+//! The `std` feature, which is on by default, adds reading from the terminal, timeouts and
+//! tracing. Without it the crate is `no_std`, and only needs `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::error::Error;
+use core::fmt;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+#[cfg(feature = "std")]
+use console::Term;  // read_char()
+
+mod bytecode;
+mod format;
+mod graph;
+mod io;
+pub mod ir;
+mod ook;
+mod transpile;
+
+pub use bytecode::BYTECODE_VERSION;
+pub use format::{decompile, format_code, minify_code};
+pub use graph::control_flow_dot;
+pub use io::{Input, IoError, Output};
+pub use ir::OptReport;
+pub use ook::ook_to_brainfuck;
+pub use transpile::{transpile_c, transpile_rust};
+
+/// The default size of the array of memory cells used by brainfuck, the same as the original.
+/// A different size can be chosen with `Interpreter::with_size`.
+pub const DATA_SIZE: usize          = 30000;
+
+/// The most loops that can be nested inside each other by default, far more than any real program
+/// needs but few enough to compile without running out of stack.
+/// A different limit can be set with `CompileOptions::max_depth`.
+pub const MAX_DEPTH: usize          = 1000;
+
+// The 8 characters that will be interpreted as brainfuck code.
+
+/// `>` increments the position of the data pointer by 1.
+/// Incrementing the data pointer past the end of the array results in an error, unless the
+/// array is growable or the data pointer wraps around.
+const INCREMENT_DP: char        = '>';
+/// `<` decrements the position of the data pointer by 1.
+/// Decrementing the data pointer below 0 results in an error, unless the data pointer wraps around.
+const DECREMENT_DP: char        = '<';
+/// `+` increments the value of the byte at the data pointer by 1.
+/// Incrementing a byte over `u8::MAX`, or 255, results in the value wrapping around to 0.
+const INCREMENT_DP_VALUE: char  = '+';
+/// `-` decrements the value of the byte at the data pointer by 1.
+/// Decrementing a byte below 0 results in the value wrapping around to `u8::MAX`,
+/// or 255.
+const DECREMENT_DP_VALUE: char  = '-';
+/// `.` writes the byte at the data pointer to stdout as a single character.
+const OUTPUT_DP: char           = '.';
+/// `,` prompts the user to input a single character, which is written to the byte at the data
+/// pointer.
+const INPUT_DP: char            = ',';
+/// `[` moves the instruction pointer forwards to the command after the matching `]` if the byte at the data
+/// pointer is 0, or else the instruction pointer is incremented by 1.
+const JUMP_FORWARD: char        = '[';
+/// `]` moves the instruction pointer backwards to the command after the matching `[` if the byte
+/// at the data pointer is non-zero, or else the instruction pointer is incremented by 1.
+const JUMP_BACK: char           = ']';
+/// `#` is a breakpoint, which is only compiled when `CompileOptions::breakpoints` is set.
+/// Otherwise it is ignored like any other character.
+const BREAKPOINT: char          = '#';
+/// `?` asserts that the byte at the data pointer isn't 0, and `=` that it is, stopping the program
+/// with an error otherwise. They are only compiled when `CompileOptions::test_ops` is set.
+/// Otherwise they are ignored like any other character.
+const ASSERT_NON_ZERO: char     = '?';
+const ASSERT_ZERO: char         = '=';
+
+/// Options that change how brainfuck code is compiled.
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    /// Compile `#` into `Op::Breakpoint`.
+    pub breakpoints: bool,
+    /// Compile `?` into `Op::AssertNonZero` and `=` into `Op::AssertZero`, so that programs can
+    /// check their own memory as they run.
+    pub test_ops: bool,
+    /// Reject any character that isn't a command or whitespace, rather than ignoring it as a
+    /// comment.
+    pub strict: bool,
+    /// Which optimizations are applied to the program.
+    pub opt_level: OptLevel,
+    /// The characters read as each of the 8 commands.
+    pub commands: Commands,
+    /// The most loops that can be nested inside each other, or `None` for no limit. Code nested
+    /// far more deeply than any real program, by tens of thousands of loops, can otherwise
+    /// overflow the stack while compiling. `MAX_DEPTH` by default.
+    pub max_depth: Option<usize>,
+    /// Close each `[` left open at the end of the code and ignore each `]` without a `[`, rather
+    /// than returning an error, so that unfinished code can still be run. See `unmatched_jumps`
+    /// for what was closed or ignored.
+    pub auto_close: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            breakpoints: false,
+            test_ops: false,
+            strict: false,
+            opt_level: OptLevel::default(),
+            commands: Commands::default(),
+            max_depth: Some(MAX_DEPTH),
+            auto_close: false,
+        }
+    }
+}
+
+/// The characters that are read as each of the 8 commands, so that variants of brainfuck which
+/// only spell the commands differently can be compiled. By default they are the usual `><+-.,[]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Commands([u8; 8]);
+
+impl Commands {
+    /// The characters for each command, in the order `><+-.,[]`.
+    /// Returns `None` unless `chars` is 8 different ASCII characters.
+    pub fn new(chars: &str) -> Option<Self> {
+        let chars: [u8; 8] = chars.as_bytes().try_into().ok()?;
+        let distinct = chars.iter().enumerate().all(|(index, c)| !chars[..index].contains(c));
+        if !chars.is_ascii() || !distinct {
+            return None;
+        }
+        Some(Commands(chars))
+    }
+
+    /// The usual character of the command `byte` is read as, if it is one of the commands.
+    pub fn command(&self, byte: u8) -> Option<char> {
+        let index = self.0.iter().position(|&c| c == byte)?;
+        let commands = [INCREMENT_DP, DECREMENT_DP, INCREMENT_DP_VALUE, DECREMENT_DP_VALUE, OUTPUT_DP, INPUT_DP,
+            JUMP_FORWARD, JUMP_BACK];
+        Some(commands[index])
+    }
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Commands(*b"><+-.,[]")
+    }
+}
+
+/// How much a program is optimized when it is compiled. Lower levels make the compiled program
+/// closer to the code, which can help when debugging the optimizer or the program itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub enum OptLevel {
+    /// Every command is compiled into an Op of its own.
+    O0,
+    /// Runs of commands are combined, and loops like `[-]` that clear a cell become `SetZero`.
+    O1,
+    /// As well as the optimizations of `O1`, loops that scan for a 0 become `ScanZero`, loops
+    /// that add or copy a cell to others become `MoveAdd`, and a `.` of a cell whose value is
+    /// already known becomes `OutputValue`.
+    #[default]
+    O2,
+    /// As well as the optimizations of `O2`, a loop that runs a small number of times known when
+    /// compiling, whose body only counts the cell down and writes bytes, is unrolled into that many
+    /// copies of its body.
+    O3,
+}
+
+/// Compile brainfuck code into a `Program` with the default `CompileOptions`.
+/// The code can be a `&str` or raw bytes, since only the bytes of the 8 commands matter.
+/// Returns an error if the code contains mismatched jump instructions.
+pub fn compile(code: impl AsRef<[u8]>) -> Result<Program, BfError> {
+    compile_with(code, &CompileOptions::default())
+}
+
+/// Compile brainfuck code into a `Program` with the given `CompileOptions`.
+/// The code doesn't need to be valid UTF-8, any bytes that aren't commands are ignored.
+/// Returns an error if the code contains mismatched jump instructions.
+pub fn compile_with(code: impl AsRef<[u8]>, options: &CompileOptions) -> Result<Program, BfError> {
+    compile_with_report(code, options).map(|(program, _)| program)
+}
+
+/// Compile brainfuck code like `compile_with`, also returning a report of the optimizations that
+/// were applied to it.
+pub fn compile_with_report(code: impl AsRef<[u8]>, options: &CompileOptions) -> Result<(Program, OptReport), BfError> {
+    let instrs = ir::parse(code, options)?;
+    let mut report = OptReport { ops_before: ir::op_count(&instrs), ..OptReport::default() };
+    let instrs = ir::optimize_with_report(instrs, options.opt_level, &mut report);
+    let program = ir::lower(&instrs);
+    report.ops_after = program.ops.len();
+    Ok((program, report))
+}
+
+/// The jumps in brainfuck code that have no other jump to match with, each with its position. Each
+/// `]` without a `[` comes first, in the order they appear in the code, and then each `[` that is
+/// still open at the end of the code, from the outermost loop in.
+pub fn unmatched_jumps(code: impl AsRef<[u8]>, commands: Commands) -> Vec<(char, SourcePosition)> {
+    let mut unmatched = Vec::new();
+    let mut open = Vec::new();
+    let mut position = SourcePosition { line: 1, column: 1 };
+    for &byte in code.as_ref() {
+        match commands.command(byte) {
+            Some(JUMP_FORWARD) => open.push(position),
+            // a `]` with a `[` to match closes it
+            Some(JUMP_BACK) if open.pop().is_none() => unmatched.push((JUMP_BACK, position)),
+            _ => (),
+        }
+        position.advance(byte);
+    }
+    unmatched.extend(open.into_iter().map(|position| (JUMP_FORWARD, position)));
+    unmatched
+}
+
+/// Compile and run brainfuck code on a fresh `Interpreter`, reading input from `input` and writing
+/// output to `output`. Returns the number of bytes written, like `Interpreter::run`.
+pub fn execute(code: &str, input: &mut impl Input, output: &mut impl Output) -> Result<usize, BfError> {
+    let program = compile(code)?;
+    let mut interpreter: Interpreter = Interpreter::new();
+    interpreter.run(&program, input, output)
+}
+
+/// Compile and run brainfuck code on a fresh `Interpreter`, returning everything it outputs as a
+/// `String`.
+#[cfg(feature = "std")]
+pub fn execute_to_string(code: &str, input: &mut impl Input) -> Result<String, Box<dyn Error>> {
+    let mut output = Vec::<u8>::new();
+    execute(code, input, &mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+/// The errors that can occur while compiling or running brainfuck code.
+#[derive(Debug)]
+pub enum BfError {
+    /// The data pointer was moved past the end of the array of memory cells.
+    DataPointerOverflow,
+    /// The data pointer was moved below 0.
+    DataPointerUnderflow,
+    /// Reading from the input failed.
+    InputError(IoError),
+    /// Writing to the output failed.
+    OutputError(IoError),
+    /// A jump instruction, `[` or `]`, has no matching jump instruction.
+    UnmatchedJump { jump: char, position: SourcePosition },
+    /// A character that isn't a command or whitespace was found while compiling in strict mode.
+    UnexpectedCharacter { byte: u8, position: SourcePosition },
+    /// A loop was nested inside more loops than `CompileOptions::max_depth` allows.
+    NestingTooDeep { limit: usize, position: SourcePosition },
+    /// Ook! code could not be translated into brainfuck, for the reason given.
+    InvalidOok { reason: &'static str, position: SourcePosition },
+    /// The program executed the maximum number of Ops allowed without finishing.
+    StepLimitReached { limit: u64 },
+    /// The program ran for longer than the timeout without finishing.
+    Timeout { timeout: Duration },
+    /// The program was stopped by setting the Interpreter's interrupt flag.
+    Interrupted,
+    /// A memory cell past the end of the array was accessed from outside of a program.
+    CellOutOfRange { index: usize, cells: usize },
+    /// Bytecode could not be loaded because it is malformed.
+    InvalidBytecode(&'static str),
+    /// Bytecode could not be loaded because it was written by a different version of the format.
+    UnsupportedBytecodeVersion { version: u8 },
+    /// An `AssertZero` or `AssertNonZero` found the cell at `index` holding `value`, when it should
+    /// have been 0 if `expected_zero` is true, or anything else if it is false.
+    AssertionFailed { expected_zero: bool, index: usize, value: u32 },
+    /// The data pointer was moved past either end of the array while guarded, see
+    /// `Interpreter::set_guard`. Holds the error, the Op that caused it and where it came from, and
+    /// the Ops executed just before it, oldest first.
+    Guarded { error: Box<BfError>, op: Op, position: SourcePosition, recent: Vec<Op> },
+}
+
+impl fmt::Display for BfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BfError::DataPointerOverflow => write!(f, "Cannot increment data pointer past the end of the data array."),
+            BfError::DataPointerUnderflow => write!(f, "Cannot decrement data pointer below 0."),
+            BfError::InputError(e) => write!(f, "Invalid character input: {e}"),
+            BfError::OutputError(e) => write!(f, "Failed to write output: {e}"),
+            BfError::UnmatchedJump { jump, position } => write!(f, "Unmatched '{jump}' at {position}."),
+            BfError::UnexpectedCharacter { byte, position } if byte.is_ascii_graphic() => {
+                write!(f, "Unexpected character '{}' at {position}.", *byte as char)
+            }
+            BfError::UnexpectedCharacter { byte, position } => write!(f, "Unexpected byte 0x{byte:02X} at {position}."),
+            BfError::NestingTooDeep { limit, position } => {
+                write!(f, "The loop at {position} is nested inside more than the limit of {limit} loops.")
+            }
+            BfError::InvalidOok { reason, position } => write!(f, "Invalid Ook! at {position}: {reason}."),
+            BfError::StepLimitReached { limit } => write!(f, "Execution stopped after reaching the limit of {limit} steps."),
+            BfError::Timeout { timeout } => write!(f, "Execution stopped after running for longer than {timeout:?}."),
+            BfError::Interrupted => write!(f, "Execution was interrupted."),
+            BfError::CellOutOfRange { index, cells } => {
+                write!(f, "Cell {index} is past the end of the {cells} memory cells.")
+            }
+            BfError::InvalidBytecode(reason) => write!(f, "Invalid bytecode: {reason}."),
+            BfError::UnsupportedBytecodeVersion { version } => write!(
+                f,
+                "Bytecode version {version} is not supported, only version {BYTECODE_VERSION} can be loaded."
+            ),
+            BfError::AssertionFailed { expected_zero: true, index, value } => {
+                write!(f, "Assertion failed: cell {index} should be 0, but it is {value}.")
+            }
+            BfError::AssertionFailed { expected_zero: false, index, .. } => {
+                write!(f, "Assertion failed: cell {index} should not be 0.")
+            }
+            BfError::Guarded { error, op, position, recent } => {
+                write!(f, "{error} The Op was {op} at {position}")?;
+                if !recent.is_empty() {
+                    let recent: Vec<String> = recent.iter().map(ToString::to_string).collect();
+                    write!(f, ", after {}", recent.join(", "))?;
+                }
+                write!(f, ".")
+            }
+        }
+    }
+}
+
+impl Error for BfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BfError::InputError(e) | BfError::OutputError(e) => Some(e),
+            BfError::Guarded { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Reads single characters from the terminal as they are typed, without waiting for enter.
+/// This is the input used by the command line interface.
+/// A character that isn't ASCII is read as its UTF-8 bytes, one byte at a time.
+/// Each character is echoed back to the terminal as it is read, unless that is turned off.
+#[cfg(feature = "std")]
+pub struct TermInput {
+    term: Term,
+    /// The bytes of the last character typed that haven't been read yet, last byte first.
+    pending: Vec<u8>,
+    echo: bool,
+}
+
+#[cfg(feature = "std")]
+impl TermInput {
+    pub fn new() -> Self {
+        TermInput {
+            term: Term::stdout(),
+            pending: Vec::new(),
+            echo: true,
+        }
+    }
+
+    /// Set whether each character is written back to the terminal once it is read, so that what
+    /// was typed can be seen. The terminal doesn't echo characters read this way by itself. On by
+    /// default.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for TermInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for TermInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() {
+            let mut bytes = [0; 4];
+            let c = self.term.read_char()?.encode_utf8(&mut bytes);
+            if self.echo {
+                self.term.write_str(c)?;
+            }
+            self.pending.extend(c.bytes().rev());
+        }
+        buf[0] = self.pending.pop().unwrap_or_default();
+        Ok(1)
+    }
+}
+
+/// Input that calls a closure for each byte, so that `,` can be fed from anywhere without using
+/// stdin. The closure returns `None` once the input has run out.
+pub struct InputFn<F: FnMut() -> Option<u8>> {
+    next: F,
+}
+
+impl<F: FnMut() -> Option<u8>> InputFn<F> {
+    pub fn new(next: F) -> Self {
+        InputFn { next }
+    }
+}
+
+// with `std` every `Read` is already `Input`, so `InputFn` can be used as either
+#[cfg(feature = "std")]
+impl<F: FnMut() -> Option<u8>> std::io::Read for InputFn<F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match (self.next)() {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<F: FnMut() -> Option<u8>> Input for InputFn<F> {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        Ok((self.next)())
+    }
+}
+
+/// Input that reads `\r\n` and a lone `\r` from another input as `\n`, so that programs expecting
+/// single `\n` line endings get them from Windows files and terminals too.
+/// A `\n` after a `\r` is skipped when it is read rather than looked for after the `\r`, so that
+/// typing a `\r` doesn't wait for another character.
+pub struct NormalizeNewlines<R> {
+    input: R,
+    /// Whether the last byte read was a `\r`.
+    after_return: bool,
+}
+
+impl<R> NormalizeNewlines<R> {
+    pub fn new(input: R) -> Self {
+        NormalizeNewlines { input, after_return: false }
+    }
+
+    /// The byte to read in place of `byte`, or `None` if it is skipped.
+    fn translate(&mut self, byte: u8) -> Option<u8> {
+        let after_return = core::mem::replace(&mut self.after_return, byte == b'\r');
+        match byte {
+            b'\r' => Some(b'\n'),
+            b'\n' if after_return => None,
+            _ => Some(byte),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> std::io::Read for NormalizeNewlines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while let Some(byte) = self.input.read_byte()? {
+            if let Some(byte) = self.translate(byte) {
+                buf[0] = byte;
+                return Ok(1);
+            }
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<R: Input> Input for NormalizeNewlines<R> {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        while let Some(byte) = self.input.read_byte()? {
+            if let Some(byte) = self.translate(byte) {
+                return Ok(Some(byte));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Output that calls a closure with each byte written by `.`, so that it can be sent anywhere,
+/// such as the text buffer of a GUI, without using stdout.
+pub struct OutputFn<F: FnMut(u8)> {
+    write: F,
+}
+
+impl<F: FnMut(u8)> OutputFn<F> {
+    pub fn new(write: F) -> Self {
+        OutputFn { write }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<F: FnMut(u8)> std::io::Write for OutputFn<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        buf.iter().for_each(|&byte| (self.write)(byte));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<F: FnMut(u8)> Output for OutputFn<F> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        bytes.iter().for_each(|&byte| (self.write)(byte));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
+
+/// Output that throws away every byte written by `.`, only counting how many there were, so that a
+/// program can be run for its effects on memory or timed without writing anything out.
+#[derive(Default)]
+pub struct Sink {
+    written: u64,
+}
+
+impl Sink {
+    pub fn new() -> Self {
+        Sink { written: 0 }
+    }
+
+    /// The number of bytes that have been written.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Output for Sink {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
+
+/// Output that writes every byte to two outputs, such as stdout and a log file, so that output can
+/// be saved while it is still being seen.
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Tee { first, second }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: std::io::Write, B: std::io::Write> std::io::Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<A: Output, B: Output> Output for Tee<A, B> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        self.first.write_bytes(bytes)?;
+        self.second.write_bytes(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+/// An enum to represent the operations within brainfuck, along with the combined operations
+/// produced by optimisation.
+/// Any brainfuck program is compiled into a list of Ops, as a lightweight way to run through the
+/// operations of the program.
+/// The jump variants hold the index of their matching jump within the list of Ops, which is
+/// filled in during compilation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    IncrementDp,
+    DecrementDp,
+    IncrementDpValue,
+    DecrementDpValue,
+    OutputDp,
+    InputDp,
+    JumpForward(usize),
+    JumpBackward(usize),
+    /// A run of `+` and `-` collapsed into the net amount to add to the byte at the data pointer.
+    AddValue(i32),
+    /// A run of `>` and `<` collapsed into the net distance to move the data pointer.
+    MovePointer(i32),
+    /// A `[-]` or `[+]` loop, which sets the byte at the data pointer to 0.
+    SetZero,
+    /// Part of a loop like `[->+<]`, which adds the byte at the data pointer multiplied by `factor`
+    /// to the byte `offset` cells away. The loop ends with a `SetZero`.
+    MoveAdd { offset: i32, factor: i32 },
+    /// A loop like `[>]` or `[<<]`, which moves the data pointer `step` cells at a time until it
+    /// reaches a byte that is 0.
+    ScanZero { step: i32 },
+    /// A `#`, which pauses execution when stepping through a program.
+    Breakpoint,
+    /// A `.` of a cell whose value is already known when compiling, as the amount added to it
+    /// since it was set to 0, so that it can be written without reading the cell.
+    OutputValue(i32),
+    /// A `?`, which stops the program with an error if the byte at the data pointer is 0.
+    AssertNonZero,
+    /// A `=`, which stops the program with an error unless the byte at the data pointer is 0.
+    AssertZero,
+}
+
+impl Op {
+    /// The name of the kind of Op, without any of the values it holds.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::IncrementDp => "IncrementDp",
+            Op::DecrementDp => "DecrementDp",
+            Op::IncrementDpValue => "IncrementDpValue",
+            Op::DecrementDpValue => "DecrementDpValue",
+            Op::OutputDp => "OutputDp",
+            Op::InputDp => "InputDp",
+            Op::JumpForward(_) => "JumpForward",
+            Op::JumpBackward(_) => "JumpBackward",
+            Op::AddValue(_) => "AddValue",
+            Op::MovePointer(_) => "MovePointer",
+            Op::SetZero => "SetZero",
+            Op::MoveAdd { .. } => "MoveAdd",
+            Op::ScanZero { .. } => "ScanZero",
+            Op::Breakpoint => "Breakpoint",
+            Op::OutputValue(_) => "OutputValue",
+            Op::AssertNonZero => "AssertNonZero",
+            Op::AssertZero => "AssertZero",
+        }
+    }
+}
+
+/// Shows the name of the Op followed by any values it holds, with jumps showing the index of the
+/// Op they jump to, such as `JumpForward -> 4` or `MoveAdd offset=1 factor=2`.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        match self {
+            Op::JumpForward(target) | Op::JumpBackward(target) => write!(f, " -> {target}"),
+            Op::AddValue(count) | Op::MovePointer(count) | Op::OutputValue(count) => write!(f, " {count}"),
+            Op::MoveAdd { offset, factor } => write!(f, " offset={offset} factor={factor}"),
+            Op::ScanZero { step } => write!(f, " step={step}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A compiled brainfuck program, which can be run any number of times by an `Interpreter`.
+#[derive(Clone, Debug)]
+pub struct Program {
+    ops: Vec<Op>,
+    positions: Vec<SourcePosition>,
+}
+
+impl Program {
+    /// The compiled list of Ops.
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// An iterator over the compiled Ops, in the order they are in the program.
+    pub fn iter(&self) -> slice::Iter<'_, Op> {
+        self.ops.iter()
+    }
+
+    /// The position in the source code of each Op in `ops`.
+    pub fn positions(&self) -> &[SourcePosition] {
+        &self.positions
+    }
+
+    /// The positions of the loops that can never end once they are entered, such as `[]` or
+    /// `[>+<]`. Only loops that clearly can't end are found: their body does no input or output,
+    /// has no loops of its own, returns the data pointer to where it started, and never changes
+    /// the byte at the data pointer. Loops that are never entered, since the byte is known to be 0
+    /// when they are reached, and any loops inside them are passed over, as with a comment loop at
+    /// the start of the program or straight after another loop. This assumes the program starts
+    /// with every cell at 0 and that the data pointer doesn't wrap around, so it says nothing
+    /// about a program run on memory that is already in use.
+    pub fn endless_loops(&self) -> Vec<SourcePosition> {
+        let mut loops = Vec::new();
+        // the end of the loop being passed over, since nothing before it can run
+        let mut skipped = None;
+        for (start, op) in self.ops.iter().enumerate() {
+            let Op::JumpForward(end) = *op else {
+                continue;
+            };
+            if skipped.is_some_and(|skipped| start < skipped) {
+                continue;
+            }
+            let entered = start.checked_sub(1).is_some_and(|before| {
+                !matches!(self.ops[before], Op::JumpBackward(_) | Op::SetZero | Op::ScanZero { .. } | Op::AssertZero)
+            });
+            if !entered {
+                skipped = Some(end);
+                continue;
+            }
+
+            // the offset of the data pointer from where it was at the start of the loop
+            let mut offset = 0;
+            let mut endless = true;
+            for &op in &self.ops[start + 1..end] {
+                match op {
+                    Op::IncrementDp => offset += 1,
+                    Op::DecrementDp => offset -= 1,
+                    Op::MovePointer(count) => offset += count,
+                    Op::IncrementDpValue | Op::DecrementDpValue | Op::AddValue(_) | Op::SetZero => {
+                        endless &= offset != 0;
+                    }
+                    Op::MoveAdd { offset: target, .. } => endless &= offset + target != 0,
+                    Op::Breakpoint => (),
+                    // a loop doing I/O is probably meant to keep going, an assertion can stop the
+                    // program, and any other loop could end it by moving the data pointer or
+                    // changing the byte
+                    Op::OutputDp | Op::OutputValue(_) | Op::InputDp | Op::JumpForward(_) | Op::JumpBackward(_)
+                    | Op::ScanZero { .. } | Op::AssertNonZero | Op::AssertZero => {
+                        endless = false;
+                    }
+                }
+            }
+            if endless && offset == 0 {
+                loops.push(self.positions[start]);
+            }
+        }
+        loops
+    }
+
+    /// The lowest and highest offsets from its starting position that the data pointer can reach
+    /// while the program runs, including the cells `MoveAdd` adds to, or `None` if they can't be
+    /// known without running it. They are only known if the loops nest properly and every loop
+    /// returns the data pointer to where it started, so that each Op always runs at the same
+    /// offset, and there is no `ScanZero`, which moves until it finds a 0.
+    pub fn pointer_bounds(&self) -> Option<(isize, isize)> {
+        let mut offset = 0;
+        let mut bounds = (0, 0);
+        // the index and the offset at the start of each loop the Op is in
+        let mut loop_offsets = Vec::new();
+        for (index, &op) in self.ops.iter().enumerate() {
+            match op {
+                Op::IncrementDp => offset += 1,
+                Op::DecrementDp => offset -= 1,
+                Op::MovePointer(count) => offset += count as isize,
+                Op::MoveAdd { offset: target, .. } => {
+                    let target = offset + target as isize;
+                    bounds = (bounds.0.min(target), bounds.1.max(target));
+                }
+                Op::JumpForward(end) => {
+                    // a `[` that skips to anywhere but its own `]` would run Ops at other offsets
+                    if self.ops.get(end) != Some(&Op::JumpBackward(index)) {
+                        return None;
+                    }
+                    loop_offsets.push((index, offset));
+                }
+                // a loop that doesn't end where it started moves the data pointer further each
+                // time, and one that isn't the innermost open loop overlaps it
+                Op::JumpBackward(start) if loop_offsets.pop() != Some((start, offset)) => return None,
+                Op::ScanZero { .. } => return None,
+                _ => (),
+            }
+            bounds = (bounds.0.min(offset), bounds.1.max(offset));
+        }
+        loop_offsets.is_empty().then_some(bounds)
+    }
+}
+
+/// Iterates over the compiled Ops, so that `for op in &program` goes through them in order.
+impl<'a> IntoIterator for &'a Program {
+    type Item = &'a Op;
+    type IntoIter = slice::Iter<'a, Op>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A line and column within brainfuck source code, both starting from 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourcePosition {
+    /// Move past the byte `byte`.
+    fn advance(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if byte & 0xC0 != 0x80 {
+            // the continuation bytes of a UTF-8 character don't start a new column
+            self.column += 1;
+        }
+    }
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// The type of a memory cell. Cells wrap around on overflow, and are converted to and from bytes
+/// for the `.` and `,` commands.
+pub trait Cell: Copy + Default + PartialEq + fmt::Debug + fmt::Display + Into<u32> {
+    /// The cell with the value 0.
+    fn zero() -> Self;
+
+    /// Whether the cell is 0.
+    fn is_zero(self) -> bool {
+        self == Self::zero()
+    }
+
+    /// Add `count` to the cell, wrapping around on overflow.
+    fn wrapping_add(self, count: Self) -> Self;
+
+    /// Subtract `count` from the cell, wrapping around below 0.
+    fn wrapping_sub(self, count: Self) -> Self;
+
+    /// Add a signed `count` to the cell, wrapping around in either direction.
+    fn wrapping_add_signed(self, count: i32) -> Self;
+
+    /// Multiply the cell by a signed `factor`, wrapping around in either direction.
+    fn wrapping_mul_signed(self, factor: i32) -> Self;
+
+    /// The cell holding the byte read by `,`.
+    fn from_byte(byte: u8) -> Self;
+
+    /// The byte written by `.`, which is the lowest byte of the cell.
+    fn to_byte(self) -> u8;
+}
+
+macro_rules! impl_cell {
+    ($($cell:ty),*) => {
+        $(
+            impl Cell for $cell {
+                fn zero() -> Self {
+                    0
+                }
+
+                fn wrapping_add(self, count: Self) -> Self {
+                    <$cell>::wrapping_add(self, count)
+                }
+
+                fn wrapping_sub(self, count: Self) -> Self {
+                    <$cell>::wrapping_sub(self, count)
+                }
+
+                fn wrapping_add_signed(self, count: i32) -> Self {
+                    // Truncating to the cell's width gives the same result as wrapping `count` times.
+                    <$cell>::wrapping_add(self, count as $cell)
+                }
+
+                fn wrapping_mul_signed(self, factor: i32) -> Self {
+                    <$cell>::wrapping_mul(self, factor as $cell)
+                }
+
+                fn from_byte(byte: u8) -> Self {
+                    byte.into()
+                }
+
+                fn to_byte(self) -> u8 {
+                    self as u8
+                }
+            }
+        )*
+    };
+}
+
+impl_cell!(u8, u16, u32);
+
+/// What the `,` command does to the byte at the data pointer once the input has run out.
+/// Brainfuck implementations differ on this, so it can be chosen to suit the program being run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EofMode {
+    /// Leave the byte as it was.
+    Unchanged,
+    /// Set the byte to 0.
+    Zero,
+    /// Set the byte to -1, which for unsigned cells is their maximum value, such as `u8::MAX`.
+    NegativeOne,
+}
+
+/// How the `.` command writes out the byte at the data pointer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    /// Write the raw byte, so that it shows as a character.
+    Char,
+    /// Write the value in decimal followed by a space.
+    Decimal,
+    /// Write the value in hexadecimal, like `0x41`, followed by a space.
+    Hex,
+}
+
+/// A copy of the memory cells and pointers of an `Interpreter`, taken with `Interpreter::snapshot`
+/// so that it can be returned to that point later with `Interpreter::restore`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct State<T: Cell = u8> {
+    data: Vec<T>,
+    data_pointer: usize,
+    high_water_mark: usize,
+    inst_pointer: usize,
+}
+
+impl<T: Cell> State<T> {
+    /// The array of memory cells when the snapshot was taken.
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    /// The position of the data pointer when the snapshot was taken.
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// The position of the instruction pointer when the snapshot was taken.
+    pub fn inst_pointer(&self) -> usize {
+        self.inst_pointer
+    }
+}
+
+/// The outcome of executing a single Op with `Interpreter::step`.
+#[derive(Debug)]
+pub enum StepResult {
+    /// The Op was executed, and execution can continue.
+    Continue,
+    /// A breakpoint was reached, and execution can continue.
+    Breakpoint,
+    /// The end of the program has been reached.
+    Halted,
+    /// The Op could not be executed.
+    Error(BfError),
+}
+
+/// A change made by an Op to the memory cells, the data pointer or the I/O, passed to the callback
+/// set with `Interpreter::set_on_event` so that a debugger can follow the program as it runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BfEvent<T: Cell = u8> {
+    /// The cell at `index` changed from `old` to `new`.
+    CellChanged { index: usize, old: T, new: T },
+    /// The data pointer moved from `from` to `to`.
+    PointerMoved { from: usize, to: usize },
+    /// A byte was written by `.`.
+    Output(u8),
+    /// A byte was read by `,`.
+    Input(u8),
+}
+
+/// The state an Op can change, taken before it is executed so that the events describing what it
+/// did can be worked out afterwards: the cell it can change and its value, the data pointer and
+/// the number of bytes read.
+type EventState<T> = (Option<(usize, T)>, usize, u64);
+
+/// The Interpreter struct holds the array of memory cells and the data and instruction pointers
+/// used to run a `Program`.
+/// The array is allocated on the heap, so that its size can be chosen at runtime.
+/// The memory cells are bytes by default, and can be any other `Cell` type.
+pub struct Interpreter<T: Cell = u8> {
+    data: Vec<T>,
+    data_pointer: usize,
+    high_water_mark: usize,
+    inst_pointer: usize,
+    eof_mode: EofMode,
+    output_mode: OutputMode,
+    growable: bool,
+    wrapping: bool,
+    trace: bool,
+    watches: Vec<usize>,
+    max_steps: Option<u64>,
+    steps: u64,
+    bytes_written: usize,
+    input_limit: Option<u64>,
+    bytes_read: u64,
+    timeout: Option<Duration>,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+    profile: Option<Vec<u64>>,
+    history: Option<Vec<Undo<T>>>,
+    /// When guarded, the instruction pointers of the last `GUARD_HISTORY` Ops, oldest first.
+    guard: Option<VecDeque<usize>>,
+    interrupt: Option<Arc<AtomicBool>>,
+    on_event: Option<Box<dyn FnMut(BfEvent<T>) + Send>>,
+    unchecked: bool,
+    /// Whether the program being run has been proven to keep the data pointer within the array,
+    /// so that the cells can be accessed without bounds checks.
+    in_bounds: bool,
+}
+
+/// What an Op changed, recorded before it is executed so that `Interpreter::step_back` can undo it.
+struct Undo<T: Cell> {
+    inst_pointer: usize,
+    data_pointer: usize,
+    high_water_mark: usize,
+    data_len: usize,
+    /// The index and previous value of the cell the Op can change, if it changes one.
+    cell: Option<(usize, T)>,
+}
+
+/// Holds the Interpreter while `run_on` runs it on a copy of `tape`, and when dropped, even while
+/// panicking, copies the cells back into `tape` and puts back the Interpreter's own state that was
+/// set aside.
+struct OnTape<'a, T: Cell> {
+    interpreter: &'a mut Interpreter<T>,
+    tape: &'a mut [T],
+    data: Vec<T>,
+    data_pointer: usize,
+    high_water_mark: usize,
+    growable: bool,
+}
+
+impl<T: Cell> Drop for OnTape<'_, T> {
+    fn drop(&mut self) {
+        let interpreter = &mut *self.interpreter;
+        // the copy can't have grown, as growing was turned off for the run
+        self.tape.copy_from_slice(&interpreter.data);
+        interpreter.data = core::mem::take(&mut self.data);
+        interpreter.data_pointer = self.data_pointer;
+        interpreter.high_water_mark = self.high_water_mark;
+        interpreter.growable = self.growable;
+        interpreter.clear_history();
+    }
+}
+
+/// Flushes the output it holds if it is dropped while panicking, which `step` doesn't get the
+/// chance to do. Any error flushing is ignored, as there is nowhere left to report it.
+struct FlushOnPanic<'a, O: Output>(&'a mut O);
+
+impl<O: Output> Drop for FlushOnPanic<'_, O> {
+    fn drop(&mut self) {
+        #[cfg(feature = "std")]
+        if std::thread::panicking() {
+            let _ = self.0.flush();
+        }
+    }
+}
+
+/// How many steps are executed between each check of the clock for the timeout and of the
+/// interrupt flag, so that the cost of checking is spread over many Ops.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// The number of Ops executed before a pointer error that are shown when guarded.
+const GUARD_HISTORY: usize = 8;
+
+/// The number of cells around the data pointer shown when debug printing an Interpreter.
+const DEBUG_WINDOW: usize = 16;
+
+/// Shows the data and instruction pointers, and the cells around the data pointer rather than the
+/// whole array, with the cell at the data pointer in brackets, e.g.
+/// `Interpreter { data_pointer: 1, inst_pointer: 5, cells: 3, tape: 0 [2] 1 }`.
+impl<T: Cell> fmt::Debug for Interpreter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Interpreter {{ data_pointer: {}, inst_pointer: {}, cells: {}, tape: ",
+            self.data_pointer,
+            self.inst_pointer,
+            self.data.len()
+        )?;
+
+        // the window is kept the same size when the data pointer is near either end
+        let end = (self.data_pointer + DEBUG_WINDOW / 2).max(DEBUG_WINDOW).min(self.data.len());
+        let start = end.saturating_sub(DEBUG_WINDOW);
+        if start > 0 {
+            write!(f, "... ")?;
+        }
+        for (index, value) in self.data.iter().enumerate().take(end).skip(start) {
+            if index > start {
+                write!(f, " ")?;
+            }
+            if index == self.data_pointer {
+                write!(f, "[{value}]")?;
+            } else {
+                write!(f, "{value}")?;
+            }
+        }
+        if end < self.data.len() {
+            write!(f, " ...")?;
+        }
+        write!(f, " }}")
+    }
+}
+
+impl<T: Cell> Default for Interpreter<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Cell> Interpreter<T> {
+    /// Create an Interpreter with `DATA_SIZE` memory cells.
+    pub fn new() -> Self {
+        Self::with_size(DATA_SIZE)
+    }
+
+    /// Create an Interpreter with `size` memory cells.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0, since there must be a cell for the data pointer to point at.
+    pub fn with_size(size: usize) -> Self {
+        assert!(size > 0, "The array of memory cells cannot be empty.");
+
+        Interpreter {
+            data: vec![T::zero(); size],
+            data_pointer: 0,
+            high_water_mark: 0,
+            inst_pointer: 0,
+            eof_mode: EofMode::Zero,
+            output_mode: OutputMode::Char,
+            growable: false,
+            wrapping: false,
+            trace: false,
+            watches: Vec::new(),
+            max_steps: None,
+            steps: 0,
+            bytes_written: 0,
+            input_limit: None,
+            bytes_read: 0,
+            timeout: None,
+            #[cfg(feature = "std")]
+            deadline: None,
+            profile: None,
+            history: None,
+            guard: None,
+            interrupt: None,
+            on_event: None,
+            unchecked: false,
+            in_bounds: false,
+        }
+    }
+
+    /// The array of memory cells.
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    /// The value of the memory cell at `index`, or `None` if it is past the end of the array.
+    pub fn cell(&self, index: usize) -> Option<T> {
+        self.data.get(index).copied()
+    }
+
+    /// Set the value of the memory cell at `index`, so that a program can be run on memory that
+    /// has been set up from outside of it.
+    /// Returns an error if `index` is past the end of the array.
+    pub fn set_cell(&mut self, index: usize, value: T) -> Result<(), BfError> {
+        let cells = self.data.len();
+        let cell = self.data.get_mut(index).ok_or(BfError::CellOutOfRange { index, cells })?;
+        *cell = value;
+        Ok(())
+    }
+
+    /// The position of the data pointer within `data`.
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// The number of Ops executed during the current run. A combined Op such as `AddValue(3)`
+    /// counts as one, however many commands it was made from, so this shows how much work the
+    /// optimized program did.
+    pub fn instructions_executed(&self) -> u64 {
+        self.steps
+    }
+
+    /// The number of bytes written to the output by `.` during the current run. In decimal and
+    /// hexadecimal output mode, this counts every byte of each number and the space after it.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// The highest position the data pointer has reached during the current run.
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+    /// The position of the instruction pointer within the list of Ops of the `Program` being run.
+    pub fn inst_pointer(&self) -> usize {
+        self.inst_pointer
+    }
+
+    /// Take a copy of the memory cells and the data and instruction pointers.
+    pub fn snapshot(&self) -> State<T> {
+        State {
+            data: self.data.clone(),
+            data_pointer: self.data_pointer,
+            high_water_mark: self.high_water_mark,
+            inst_pointer: self.inst_pointer,
+        }
+    }
+
+    /// Return the memory cells and the data and instruction pointers to how they were when `state`
+    /// was taken, so that stepping continues from that point. The settings of the Interpreter,
+    /// the count of steps and the profile are left as they are.
+    pub fn restore(&mut self, state: State<T>) {
+        self.data = state.data;
+        self.data_pointer = state.data_pointer;
+        self.high_water_mark = state.high_water_mark;
+        self.inst_pointer = state.inst_pointer;
+        self.clear_history();
+    }
+
+    /// Set every memory cell to 0 and move the data and instruction pointers back to 0, as if the
+    /// Interpreter had just been created.
+    pub fn reset(&mut self) {
+        self.data.fill(T::zero());
+        self.data_pointer = 0;
+        self.high_water_mark = 0;
+        self.inst_pointer = 0;
+        self.clear_history();
+    }
+
+    /// Write `bytes` into the memory cells starting at cell 0, leaving the rest as they are, so
+    /// that a program can start with data already in memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more bytes than memory cells.
+    pub fn load(&mut self, bytes: &[u8]) {
+        assert!(bytes.len() <= self.data.len(), "There are more bytes to load than memory cells.");
+
+        for (cell, &byte) in self.data.iter_mut().zip(bytes) {
+            *cell = T::from_byte(byte);
+        }
+    }
+
+    /// Move the instruction pointer back to the start of the program, so that it can be stepped
+    /// through again with `step`. This also restarts the count of steps towards the maximum, the
+    /// counts of bytes written and read, the timeout, and the high water mark of the data pointer.
+    pub fn rewind(&mut self) {
+        self.inst_pointer = 0;
+        self.high_water_mark = self.data_pointer;
+        self.steps = 0;
+        self.bytes_written = 0;
+        self.bytes_read = 0;
+        #[cfg(feature = "std")]
+        {
+            self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.clear();
+        }
+        if let Some(guard) = &mut self.guard {
+            guard.clear();
+        }
+        self.clear_history();
+    }
+
+    /// Set whether the array of memory cells grows when the data pointer moves past its end,
+    /// rather than that being an error. Off by default.
+    pub fn set_growable(&mut self, growable: bool) {
+        self.growable = growable;
+    }
+
+    /// Set whether the data pointer wraps around the ends of the array of memory cells, so that
+    /// moving past the last cell goes to cell 0 and moving below cell 0 goes to the last cell,
+    /// rather than that being an error. If the array is also growable, it grows at the end rather
+    /// than wrapping. Off by default.
+    pub fn set_wrapping(&mut self, wrapping: bool) {
+        self.wrapping = wrapping;
+    }
+
+    /// Set whether `run` skips bounds checks on the memory cells, for speed. They are only skipped
+    /// if `Program::pointer_bounds` proves that the program can't move the data pointer outside
+    /// of the array from where it starts, and otherwise the program runs with them as usual. Off
+    /// by default.
+    pub fn set_unchecked(&mut self, unchecked: bool) {
+        self.unchecked = unchecked;
+    }
+
+    /// Set whether each Op is written to stderr as it is executed, along with the instruction
+    /// pointer, data pointer and the byte at the data pointer. Off by default, and only has an
+    /// effect with the `std` feature.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Set the indices of cells to watch. Whenever an Op changes the value of a watched cell, the
+    /// instruction pointer of the Op and the cell's old and new values are written to stderr.
+    /// No cells are watched by default, and this only has an effect with the `std` feature.
+    pub fn set_watches(&mut self, watches: Vec<usize>) {
+        self.watches = watches;
+    }
+
+    /// Set the maximum number of Ops a program can execute before it is stopped with an error, or
+    /// `None` for no limit. Unlimited by default.
+    pub fn set_max_steps(&mut self, max_steps: Option<u64>) {
+        self.max_steps = max_steps;
+    }
+
+    /// Set the most bytes `,` reads from the input during a run, or `None` for no limit. Once that
+    /// many have been read, `,` acts as if the input has run out, following the `EofMode`, without
+    /// reading any more. Unlimited by default.
+    pub fn set_input_limit(&mut self, input_limit: Option<u64>) {
+        self.input_limit = input_limit;
+    }
+
+    /// Set how long a program can run for before it is stopped with an error, or `None` for no
+    /// limit. The time is measured from the start of `run`, or from `rewind` when stepping.
+    /// Unlimited by default, and only has an effect with the `std` feature.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Set a flag that stops the program with `BfError::Interrupted` when it is set to `true`, such
+    /// as from a Ctrl-C handler. The flag is checked every so often rather than before every Op,
+    /// and is set back to `false` once the program has stopped. There is no flag by default.
+    pub fn set_interrupt(&mut self, interrupt: Option<Arc<AtomicBool>>) {
+        self.interrupt = interrupt;
+    }
+
+    /// Set a callback that is called with a `BfEvent` for each change an Op makes as it runs, in
+    /// the order input, cell, data pointer and output, or `None` for no callback. There is no
+    /// callback by default, and nothing extra is done to work out the events without one.
+    pub fn set_on_event(&mut self, on_event: Option<Box<dyn FnMut(BfEvent<T>) + Send>>) {
+        self.on_event = on_event;
+    }
+
+    /// Set whether to count how many times each Op of the program is executed. The counts are
+    /// restarted with each run, and can be read with `profile`. Off by default.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profile = profiling.then(Vec::new);
+    }
+
+    /// When profiling, how many times each Op of the last program run has been executed, indexed
+    /// the same as `Program::ops`.
+    pub fn profile(&self) -> Option<&[u64]> {
+        self.profile.as_deref()
+    }
+
+    /// Set whether to keep track of the last few Ops executed, so that moving the data pointer past
+    /// either end of the array is reported as `BfError::Guarded`, showing where in the code the Op
+    /// that moved it came from and the Ops that led up to it. Off by default.
+    pub fn set_guard(&mut self, guard: bool) {
+        self.guard = guard.then(VecDeque::new);
+    }
+
+    /// Set whether `step` records what each Op changes, so that it can be undone with `step_back`.
+    /// The history is cleared by `rewind`, `reset` and `restore`. Off by default.
+    pub fn set_history(&mut self, history: bool) {
+        self.history = history.then(Vec::new);
+    }
+
+    fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// Undo the last Op executed by `step`, returning the memory cells and pointers to how they
+    /// were before it. Input that was read is not put back, but the cell it was read into is
+    /// restored, and output that was written stays written.
+    /// Returns `false` if there is nothing to undo, either because no Ops have been executed or
+    /// because the history isn't being recorded.
+    pub fn step_back(&mut self) -> bool {
+        let Some(undo) = self.history.as_mut().and_then(Vec::pop) else {
+            return false;
+        };
+
+        // cells added by growing the array are removed again
+        self.data.truncate(undo.data_len);
+        if let Some((index, value)) = undo.cell {
+            self.data[index] = value;
+        }
+        self.inst_pointer = undo.inst_pointer;
+        self.data_pointer = undo.data_pointer;
+        self.high_water_mark = undo.high_water_mark;
+        self.steps = self.steps.saturating_sub(1);
+        true
+    }
+
+    /// Set what the `,` command does once the input has run out. Defaults to `EofMode::Zero`.
+    pub fn set_eof_mode(&mut self, eof_mode: EofMode) {
+        self.eof_mode = eof_mode;
+    }
+
+    /// Set how the `.` command writes out the byte at the data pointer. Defaults to
+    /// `OutputMode::Char`.
+    pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+    }
+
+    /// Run the compiled list of instructions like `run`, capturing the output into a `String`
+    /// rather than writing it out.
+    #[cfg(feature = "std")]
+    pub fn run_to_string(&mut self, program: &Program, input: &mut impl Input) -> Result<String, Box<dyn Error>> {
+        let mut output = Vec::<u8>::new();
+        self.run(program, input, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    /// Reset the instruction pointer to 0 and run the compiled list of instructions.
+    /// The memory cells and data pointer are left as they are, so running again continues from
+    /// the state the last run finished in.
+    /// Breakpoints are passed over, use `step` to stop at them.
+    /// The output is flushed however the program stops, whether it finishes, stops with an error,
+    /// or with the `std` feature, panics, so that everything it wrote before stopping is seen.
+    /// Returns the number of bytes written, as counted by `bytes_written`.
+    pub fn run(&mut self, program: &Program, input: &mut impl Input, output: &mut impl Output) -> Result<usize, BfError> {
+        let output = FlushOnPanic(output);
+        self.rewind();
+        self.in_bounds = self.unchecked && program.pointer_bounds().is_some_and(|(lowest, highest)| {
+            let start = self.data_pointer as isize;
+            start + lowest >= 0 && start + highest < self.data.len() as isize
+        });
+
+        let result = loop {
+            match self.step(program, input, &mut *output.0) {
+                StepResult::Continue | StepResult::Breakpoint => (),
+                StepResult::Halted => break Ok(self.bytes_written),
+                StepResult::Error(e) => break Err(e),
+            }
+        };
+        // `step` can be called with any program afterwards, which hasn't been checked
+        self.in_bounds = false;
+        result
+    }
+
+    /// Run the program like `run`, but on `tape` as the memory cells rather than the Interpreter's
+    /// own, so that the caller can set up the memory beforehand and look at it afterwards. The data
+    /// pointer starts at cell 0 of the tape, and moving it past either end is an error as usual,
+    /// even if the Interpreter can grow its memory, since the tape can't grow. The tape holds
+    /// whatever the program left in it even if it stopped with an error or panicked.
+    /// The Interpreter's own memory cells and data pointer are left as they were, and the history
+    /// for `step_back` is cleared, as it would undo changes to the tape.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tape` is empty, since there must be a cell for the data pointer to point at.
+    pub fn run_on(
+        &mut self,
+        program: &Program,
+        tape: &mut [T],
+        input: &mut impl Input,
+        output: &mut impl Output,
+    ) -> Result<usize, BfError> {
+        assert!(!tape.is_empty(), "The tape cannot be empty.");
+
+        // the tape is run on as a copy, which the guard copies back however the program stops
+        let guard = OnTape {
+            data: core::mem::replace(&mut self.data, tape.to_vec()),
+            data_pointer: core::mem::replace(&mut self.data_pointer, 0),
+            high_water_mark: core::mem::replace(&mut self.high_water_mark, 0),
+            growable: core::mem::replace(&mut self.growable, false),
+            interpreter: self,
+            tape,
+        };
+        guard.interpreter.run(program, input, output)
+    }
+
+    /// Execute the single Op of `program` at the instruction pointer, and move the instruction
+    /// pointer on to the next Op to execute.
+    /// Output is only flushed before reading input, and when the program stops for any reason,
+    /// so `output` can be buffered.
+    pub fn step(&mut self, program: &Program, input: &mut impl Input, output: &mut impl Output) -> StepResult {
+        let result = self.step_op(program, input, output);
+        if let StepResult::Continue = result {
+            return result;
+        }
+
+        // the program has stopped, so everything it has written so far should be seen
+        match output.flush() {
+            Err(e) if !matches!(result, StepResult::Error(_)) => StepResult::Error(BfError::OutputError(e)),
+            _ => result,
+        }
+    }
+
+    fn step_op(&mut self, program: &Program, input: &mut impl Input, output: &mut impl Output) -> StepResult {
+        // Jump instructions will move the instruction pointer around the program
+        // and any other operation will increment it by 1.
+        // So the program is finished once the list of operations is exhausted.
+        let Some(&op) = program.ops.get(self.inst_pointer) else {
+            return StepResult::Halted;
+        };
+
+        if let Some(limit) = self.max_steps {
+            if self.steps >= limit {
+                return StepResult::Error(BfError::StepLimitReached { limit });
+            }
+        }
+        if self.steps.is_multiple_of(CHECK_INTERVAL) {
+            #[cfg(feature = "std")]
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return StepResult::Error(BfError::Timeout { timeout: self.timeout.unwrap_or_default() });
+                }
+            }
+            // the flag is cleared, so that the next run isn't interrupted straight away
+            if let Some(interrupt) = &self.interrupt {
+                if interrupt.swap(false, Ordering::Relaxed) {
+                    return StepResult::Error(BfError::Interrupted);
+                }
+            }
+        }
+        self.steps += 1;
+
+        if let Some(profile) = &mut self.profile {
+            if profile.len() < program.ops.len() {
+                profile.resize(program.ops.len(), 0);
+            }
+            profile[self.inst_pointer] += 1;
+        }
+
+        #[cfg(feature = "std")]
+        if self.trace {
+            eprintln!("{:04} {:?} dp={} cell={}", self.inst_pointer, op, self.data_pointer, self.data[self.data_pointer]);
+        }
+
+        if self.history.is_some() {
+            self.record_undo(op);
+        }
+
+        // only the cell an Op can change needs to be compared, and only if it is watched
+        #[cfg(feature = "std")]
+        let watched = if self.watches.is_empty() {
+            None
+        } else {
+            self.changed_cell(op)
+                .filter(|index| self.watches.contains(index))
+                .map(|index| (self.inst_pointer, index, self.data.get(index).copied().unwrap_or_default()))
+        };
+
+        let events: Option<EventState<T>> = self.on_event.is_some().then(|| {
+            let cell = self.changed_cell(op).map(|index| (index, self.data.get(index).copied().unwrap_or_default()));
+            (cell, self.data_pointer, self.bytes_read)
+        });
+
+        let inst_pointer = self.inst_pointer;
+        let result = self.execute_op(op, input, output);
+        if let (Ok(()), Some(before)) = (&result, events) {
+            self.emit_events(op, before);
+        }
+        let result = match (result, &mut self.guard) {
+            (Err(error @ (BfError::DataPointerOverflow | BfError::DataPointerUnderflow)), Some(guard)) => {
+                Err(BfError::Guarded {
+                    error: Box::new(error),
+                    op,
+                    position: program.positions[inst_pointer],
+                    recent: guard.iter().map(|&index| program.ops[index]).collect(),
+                })
+            }
+            (result, Some(guard)) => {
+                if guard.len() == GUARD_HISTORY {
+                    guard.pop_front();
+                }
+                guard.push_back(inst_pointer);
+                result
+            }
+            (result, None) => result,
+        };
+
+        #[cfg(feature = "std")]
+        if let (Ok(()), Some((inst_pointer, index, old))) = (&result, watched) {
+            let new = self.data.get(index).copied().unwrap_or_default();
+            if new != old {
+                eprintln!("{inst_pointer:04} watch cell {index}: {old} -> {new}");
+            }
+        }
+
+        match result {
+            Ok(()) if op == Op::Breakpoint => StepResult::Breakpoint,
+            Ok(()) => StepResult::Continue,
+            Err(e) => StepResult::Error(e),
+        }
+    }
+
+    /// Call the event callback for each change `op` made, given the state from `before` it was
+    /// executed.
+    fn emit_events(&mut self, op: Op, (cell, data_pointer, bytes_read): EventState<T>) {
+        let Some(on_event) = &mut self.on_event else {
+            return;
+        };
+        let current = self.data[self.data_pointer];
+
+        // a `,` that found the input had run out read nothing
+        if op == Op::InputDp && self.bytes_read > bytes_read {
+            on_event(BfEvent::Input(current.to_byte()));
+        }
+        if let Some((index, old)) = cell {
+            let new = self.data.get(index).copied().unwrap_or_default();
+            if new != old {
+                on_event(BfEvent::CellChanged { index, old, new });
+            }
+        }
+        if self.data_pointer != data_pointer {
+            on_event(BfEvent::PointerMoved { from: data_pointer, to: self.data_pointer });
+        }
+        match op {
+            Op::OutputDp => on_event(BfEvent::Output(current.to_byte())),
+            Op::OutputValue(value) => on_event(BfEvent::Output(T::zero().wrapping_add_signed(value).to_byte())),
+            _ => (),
+        }
+    }
+
+    /// The index of the cell `op` can change when executed, if it changes one. The index may be
+    /// past the end of the array if it would be grown.
+    fn changed_cell(&self, op: Op) -> Option<usize> {
+        match op {
+            Op::IncrementDpValue | Op::DecrementDpValue | Op::InputDp | Op::AddValue(_) | Op::SetZero => {
+                Some(self.data_pointer)
+            }
+            Op::MoveAdd { offset, .. } => usize::try_from(self.wrap_target(self.data_pointer as isize + offset as isize)).ok(),
+            _ => None,
+        }
+    }
+
+    fn record_undo(&mut self, op: Op) {
+        let index = self.changed_cell(op).filter(|&index| index < self.data.len());
+        let undo = Undo {
+            inst_pointer: self.inst_pointer,
+            data_pointer: self.data_pointer,
+            high_water_mark: self.high_water_mark,
+            data_len: self.data.len(),
+            cell: index.map(|index| (index, self.data[index])),
+        };
+
+        if let Some(history) = &mut self.history {
+            history.push(undo);
+        }
+    }
+
+    fn execute_op(&mut self, op: Op, input: &mut impl Input, output: &mut impl Output) -> Result<(), BfError> {
+        match op {
+            Op::IncrementDp => {
+                self.increment_dp()?;
+                self.inst_pointer += 1;
+            }
+            Op::DecrementDp => {
+                self.decrement_dp()?;
+                self.inst_pointer += 1;
+            }
+            Op::IncrementDpValue => {
+                self.increment_dp_value();
+                self.inst_pointer += 1;
+            }
+            Op::DecrementDpValue => {
+                self.decrement_dp_value();
+                self.inst_pointer += 1;
+            }
+            Op::OutputDp => {
+                let value = *self.current();
+                self.output_value(value, output)?;
+                self.inst_pointer += 1;
+            }
+            Op::OutputValue(value) => {
+                self.output_value(T::zero().wrapping_add_signed(value), output)?;
+                self.inst_pointer += 1;
+            }
+            Op::InputDp => {
+                self.input_dp(input, output)?;
+                self.inst_pointer += 1;
+            }
+            Op::JumpForward(end) => self.jump_forward(end),
+            Op::JumpBackward(start) => self.jump_backward(start),
+            Op::AddValue(count) => {
+                self.add_value(count);
+                self.inst_pointer += 1;
+            }
+            Op::MovePointer(count) => {
+                self.move_pointer(count)?;
+                self.inst_pointer += 1;
+            }
+            Op::SetZero => {
+                *self.current() = T::zero();
+                self.inst_pointer += 1;
+            }
+            Op::MoveAdd { offset, factor } => {
+                self.move_add(offset, factor)?;
+                self.inst_pointer += 1;
+            }
+            Op::ScanZero { step } => {
+                // a scan that can't find a 0 would be a loop that never ends, so it carries on
+                // scanning the next time the Op is executed
+                if self.scan_zero(step)? {
+                    self.inst_pointer += 1;
+                }
+            }
+            Op::Breakpoint => self.inst_pointer += 1,
+            Op::AssertNonZero | Op::AssertZero => {
+                let value = *self.current();
+                let expected_zero = op == Op::AssertZero;
+                if value.is_zero() != expected_zero {
+                    return Err(BfError::AssertionFailed { expected_zero, index: self.data_pointer, value: value.into() });
+                }
+                self.inst_pointer += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+
+    #[inline]
+    fn increment_dp(&mut self) -> Result<(), BfError> {
+        if !self.in_bounds && self.data_pointer == self.data.len() - 1 {
+            if self.growable {
+                self.data.push(T::zero());
+            } else if self.wrapping {
+                self.data_pointer = 0;
+                return Ok(());
+            } else {
+                return Err(BfError::DataPointerOverflow);
+            }
+        }
+        self.data_pointer += 1;
+        self.high_water_mark = self.high_water_mark.max(self.data_pointer);
+        Ok(())
+    }
+
+    #[inline]
+    fn decrement_dp(&mut self) -> Result<(), BfError> {
+        if !self.in_bounds && self.data_pointer == 0 {
+            if !self.wrapping {
+                return Err(BfError::DataPointerUnderflow);
+            }
+            self.data_pointer = self.data.len() - 1;
+            self.high_water_mark = self.data_pointer;
+            return Ok(());
+        }
+        self.data_pointer -= 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn move_pointer(&mut self, count: i32) -> Result<(), BfError> {
+        // Only the final position needs bounds checking, since a run is only combined into one
+        // move when all of it goes the same direction.
+        self.data_pointer = self.cell_at(count)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn move_add(&mut self, offset: i32, factor: i32) -> Result<(), BfError> {
+        // the loop this came from doesn't run at all when the byte is 0, so it can't go out of bounds
+        let value = *self.current();
+        if value.is_zero() {
+            return Ok(());
+        }
+        let target = self.cell_at(offset)?;
+        let cell = self.cell_mut(target);
+        *cell = cell.wrapping_add(value.wrapping_mul_signed(factor));
+        Ok(())
+    }
+
+    /// Move the data pointer `step` cells at a time until it reaches a 0. Returns `false` if the
+    /// data pointer wraps around and has come back to where it started without finding one.
+    fn scan_zero(&mut self, step: i32) -> Result<bool, BfError> {
+        if self.wrapping {
+            // every cell the data pointer can reach has been checked once it has moved as many
+            // times as there are cells
+            for _ in 0..self.data.len() {
+                if self.data[self.data_pointer].is_zero() {
+                    return Ok(true);
+                }
+                self.data_pointer = self.cell_at(step)?;
+            }
+            return Ok(self.data[self.data_pointer].is_zero());
+        }
+
+        // scanning one cell at a time is the common case, and can search the cells directly
+        if step == 1 {
+            match self.data[self.data_pointer..].iter().position(|value| value.is_zero()) {
+                Some(distance) => {
+                    self.data_pointer += distance;
+                    self.high_water_mark = self.high_water_mark.max(self.data_pointer);
+                }
+                None => {
+                    // there is no 0 left, so the pointer moves off the end of the array
+                    self.data_pointer = self.data.len() - 1;
+                    self.data_pointer = self.cell_at(1)?;
+                }
+            }
+            return Ok(true);
+        }
+
+        while !self.data[self.data_pointer].is_zero() {
+            self.data_pointer = self.cell_at(step)?;
+        }
+        Ok(true)
+    }
+
+    /// The index `target` becomes once it has wrapped around the ends of the array, if the data
+    /// pointer wraps and the array wouldn't grow to reach it instead.
+    #[inline]
+    fn wrap_target(&self, target: isize) -> isize {
+        let len = self.data.len() as isize;
+        let grows = self.growable && target >= len;
+        if self.wrapping && !grows {
+            target.rem_euclid(len)
+        } else {
+            target
+        }
+    }
+
+    /// The index of the cell `offset` cells away from the data pointer, adding cells if it's past
+    /// the end and the array can grow, or wrapping around if the data pointer wraps. The high
+    /// water mark is raised to include it, or if it wrapped, to the last cell, which the data
+    /// pointer passed on the way as it does when moving one cell at a time.
+    #[inline]
+    fn cell_at(&mut self, offset: i32) -> Result<usize, BfError> {
+        if self.in_bounds {
+            let target = self.data_pointer.wrapping_add_signed(offset as isize);
+            self.high_water_mark = self.high_water_mark.max(target);
+            return Ok(target);
+        }
+        let unwrapped = self.data_pointer as isize + offset as isize;
+        let target = self.wrap_target(unwrapped);
+        if target < 0 {
+            return Err(BfError::DataPointerUnderflow);
+        }
+        if target as usize >= self.data.len() {
+            if !self.growable {
+                return Err(BfError::DataPointerOverflow);
+            }
+            self.data.resize(target as usize + 1, T::zero());
+        }
+        let reached = if target == unwrapped { target as usize } else { self.data.len() - 1 };
+        self.high_water_mark = self.high_water_mark.max(reached);
+        Ok(target as usize)
+    }
+
+    /// The cell at the data pointer.
+    #[inline]
+    fn current(&mut self) -> &mut T {
+        self.cell_mut(self.data_pointer)
+    }
+
+    /// The cell at `index`, without a bounds check if the program has been proven to stay within
+    /// the array.
+    #[inline]
+    fn cell_mut(&mut self, index: usize) -> &mut T {
+        if self.in_bounds {
+            // SAFETY: `run` only sets `in_bounds` once `Program::pointer_bounds` has shown that
+            // every cell the program can reach from where the data pointer started is in the array
+            unsafe { self.data.get_unchecked_mut(index) }
+        } else {
+            &mut self.data[index]
+        }
+    }
+
+    #[inline]
+    fn add_value(&mut self, count: i32) {
+        let cell = self.current();
+        *cell = cell.wrapping_add_signed(count);
+    }
+
+    #[inline]
+    fn increment_dp_value(&mut self) {
+        let cell = self.current();
+        *cell = cell.wrapping_add(T::from_byte(1));
+    }
+
+    #[inline]
+    fn decrement_dp_value(&mut self) {
+        let cell = self.current();
+        *cell = cell.wrapping_sub(T::from_byte(1));
+    }
+
+    fn input_dp(&mut self, input: &mut impl Input, output: &mut impl Output) -> Result<(), BfError> {
+        // anything written so far may be a prompt for this input, so it needs to be seen first
+        output.flush().map_err(BfError::OutputError)?;
+
+        // past the limit, the input is left unread as if it had run out
+        let limited = self.input_limit.is_some_and(|limit| self.bytes_read >= limit);
+        let read = if limited { Ok(None) } else { input.read_byte() };
+        match read {
+            Ok(Some(byte)) => {
+                self.data[self.data_pointer] = T::from_byte(byte);
+                self.bytes_read += 1;
+            }
+            Ok(None) => match self.eof_mode {
+                EofMode::Unchanged => (),
+                EofMode::Zero => self.data[self.data_pointer] = T::zero(),
+                EofMode::NegativeOne => self.data[self.data_pointer] = T::zero().wrapping_sub(T::from_byte(1)),
+            },
+            Err(e) => return Err(BfError::InputError(e)),
+        }
+        Ok(())
+    }
+
+    fn output_value(&mut self, value: T, output: &mut impl Output) -> Result<(), BfError> {
+        // Write the raw byte so that programs control their own newlines. The output is flushed
+        // before input is read, so it stays in order with any input prompts.
+        let byte = [value.to_byte()];
+        let text;
+        let bytes = match self.output_mode {
+            OutputMode::Char => &byte[..],
+            OutputMode::Decimal => {
+                text = format!("{value} ");
+                text.as_bytes()
+            }
+            OutputMode::Hex => {
+                text = format!("0x{:02X} ", value.into());
+                text.as_bytes()
+            }
+        };
+        output.write_bytes(bytes).map_err(BfError::OutputError)?;
+        self.bytes_written += bytes.len();
+        Ok(())
+    }
+
+    #[inline]
+    fn jump_forward(&mut self, end: usize) {
+        // Called when we encounter a jump forward instruction.
+        // If the byte at the data pointer is 0, we need to jump just beyond the corresponding jump
+        // back instruction at `end`.
+        if self.current().is_zero() {
+            self.inst_pointer = end + 1;
+        } else {
+            // or else just increment by 1.
+            self.inst_pointer += 1;
+        }
+    }
+
+    #[inline]
+    fn jump_backward(&mut self, start: usize) {
+        // Called when we encounter a jump backward instruction.
+        // If the byte at the data pointer is non 0, we go back just beyond the corresponding jump
+        // forward instruction at `start`.
+        if !self.current().is_zero() {
+            self.inst_pointer = start + 1;
+        } else {
+            self.inst_pointer += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::{String, ToString};
+
+    /// Compile `code` and run it on `interpreter`, without any input or output.
+    fn run_on(interpreter: &mut Interpreter, code: &str) -> Result<usize, BfError> {
+        interpreter.run(&compile(code)?, &mut &b""[..], &mut Vec::new())
+    }
+
+    fn run(code: &str, input: &[u8]) -> Result<Vec<u8>, BfError> {
+        let program = compile(code)?;
+        let mut output = Vec::new();
+        Interpreter::<u8>::new().run(&program, &mut &input[..], &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn cells_are_unsigned_bytes() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        run_on(&mut interpreter, &"+".repeat(200)).unwrap();
+        assert_eq!(interpreter.data()[0], 200);
+        run_on(&mut interpreter, &"+".repeat(56)).unwrap();
+        assert_eq!(interpreter.data()[0], 0);
+        run_on(&mut interpreter, "-").unwrap();
+        assert_eq!(interpreter.data()[0], 255);
+    }
+
+    #[test]
+    fn small_arrays_hold_their_size() {
+        let mut interpreter: Interpreter = Interpreter::with_size(4);
+        run_on(&mut interpreter, ">>>+").unwrap();
+        assert_eq!(interpreter.data(), [0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn small_arrays_stop_at_their_end() {
+        let result = run_on(&mut Interpreter::with_size(4), ">>>>");
+        assert!(matches!(result, Err(BfError::DataPointerOverflow)));
+    }
+
+    #[test]
+    fn coalesces_runs() {
+        assert_eq!(compile("+++++>>").unwrap().ops(), [Op::AddValue(5), Op::MovePointer(2)]);
+        assert_eq!(compile("+-<<").unwrap().ops(), [Op::MovePointer(-2)]);
+    }
+
+    #[test]
+    fn only_coalesces_moves_in_one_direction() {
+        assert_eq!(compile(">>><<").unwrap().ops(), [Op::MovePointer(3), Op::MovePointer(-2)]);
+    }
+
+    #[test]
+    fn moving_back_and_forth_at_cell_0_is_an_error() {
+        let result = execute("<>", &mut &b""[..], &mut Vec::new());
+        assert!(matches!(result, Err(BfError::DataPointerUnderflow)));
+    }
+
+    #[test]
+    fn clear_loops() {
+        assert_eq!(compile("[-]").unwrap().ops(), [Op::SetZero]);
+        assert_eq!(compile("[+]").unwrap().ops(), [Op::SetZero]);
+        assert!(!compile("[->]").unwrap().ops().contains(&Op::SetZero));
+        assert!(!compile("[--]").unwrap().ops().contains(&Op::SetZero));
+        let mut interpreter: Interpreter = Interpreter::new();
+        run_on(&mut interpreter, "+++[-]>++[+]").unwrap();
+        assert_eq!(&interpreter.data()[..2], [0, 0]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn input_from_a_reader() {
+        assert_eq!(execute_to_string(",.,.,.,.,.", &mut &b"hello"[..]).unwrap(), "hello");
+        assert_eq!(execute_to_string(",[.,]", &mut &b"hello"[..]).unwrap(), "hello");
+        assert_eq!(execute_to_string(",+.", &mut &b"a"[..]).unwrap(), "b");
+    }
+
+    #[test]
+    fn eof_modes() {
+        let program = compile("+++++,").unwrap();
+        for (eof_mode, value) in [(EofMode::Unchanged, 5), (EofMode::Zero, 0), (EofMode::NegativeOne, 255)] {
+            let mut interpreter: Interpreter = Interpreter::new();
+            interpreter.set_eof_mode(eof_mode);
+            interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap();
+            assert_eq!(interpreter.data()[0], value);
+        }
+        // Zero is the default
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.data()[0], 0);
+    }
+
+    #[test]
+    fn step_through_a_program() {
+        let program = compile("+>++").unwrap();
+        let mut interpreter: Interpreter = Interpreter::new();
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        assert!(matches!(interpreter.step(&program, &mut input, &mut output), StepResult::Continue));
+        assert_eq!((interpreter.data()[0], interpreter.inst_pointer()), (1, 1));
+        assert!(matches!(interpreter.step(&program, &mut input, &mut output), StepResult::Continue));
+        assert_eq!((interpreter.data_pointer(), interpreter.inst_pointer()), (1, 2));
+        assert!(matches!(interpreter.step(&program, &mut input, &mut output), StepResult::Continue));
+        assert_eq!(&interpreter.data()[..2], [1, 2]);
+        assert!(matches!(interpreter.step(&program, &mut input, &mut output), StepResult::Halted));
+        assert_eq!(interpreter.inst_pointer(), 3);
+    }
+
+    #[test]
+    fn pointer_errors() {
+        let run = |code| execute(code, &mut &b""[..], &mut Vec::new());
+        assert!(matches!(run("<"), Err(BfError::DataPointerUnderflow)));
+        assert!(matches!(run("+[<]"), Err(BfError::DataPointerUnderflow)));
+        let result = run_on(&mut Interpreter::with_size(2), ">>");
+        assert!(matches!(result, Err(BfError::DataPointerOverflow)));
+        assert_eq!(BfError::DataPointerUnderflow.to_string(), "Cannot decrement data pointer below 0.");
+    }
+
+    #[test]
+    fn compile_once_run_twice() {
+        let program = compile(",[->+<]>.").unwrap();
+        let mut first: Interpreter = Interpreter::new();
+        let mut second: Interpreter = Interpreter::with_size(2);
+        run_on(&mut second, ">++++++++++<").unwrap();
+
+        let mut output = Vec::new();
+        first.run(&program, &mut &[3][..], &mut output).unwrap();
+        second.run(&program, &mut &[4][..], &mut output).unwrap();
+        assert_eq!(output, [3, 14]);
+    }
+
+    #[test]
+    fn growable_array() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_growable(true);
+        run_on(&mut interpreter, &format!("{}+", ">".repeat(DATA_SIZE + 1))).unwrap();
+        assert_eq!(interpreter.data_pointer(), DATA_SIZE + 1);
+        assert_eq!(interpreter.data()[DATA_SIZE + 1], 1);
+
+        // there is still nothing below cell 0
+        let result = run_on(&mut interpreter, &"<".repeat(DATA_SIZE + 2));
+        assert!(matches!(result, Err(BfError::DataPointerUnderflow)));
+    }
+
+    #[test]
+    fn unmatched_jump_positions() {
+        let error = compile("[[]").unwrap_err();
+        assert!(matches!(error, BfError::UnmatchedJump { jump: '[', position: SourcePosition { line: 1, column: 1 } }));
+        assert_eq!(error.to_string(), "Unmatched '[' at line 1, column 1.");
+
+        let error = compile("+\n  comment ]").unwrap_err();
+        assert!(matches!(error, BfError::UnmatchedJump { jump: ']', position: SourcePosition { line: 2, column: 11 } }));
+    }
+
+    #[test]
+    fn breakpoints_off() {
+        let with = compile("++#++[>#+<-]#>.").unwrap();
+        let without = compile("++++[>+<-]>.").unwrap();
+        assert_eq!(with.ops(), without.ops());
+
+        // run passes over breakpoints even when they are compiled in
+        let options = CompileOptions { breakpoints: true, ..CompileOptions::default() };
+        let program = compile_with("++#++[>#+<-]#>.", &options).unwrap();
+        assert!(program.ops().contains(&Op::Breakpoint));
+        let mut output = Vec::new();
+        Interpreter::<u8>::new().run(&program, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, [4]);
+    }
+
+    #[test]
+    fn reset_restores_the_initial_state() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        run_on(&mut interpreter, "+++>++>+").unwrap();
+        assert_eq!(interpreter.data_pointer(), 2);
+
+        interpreter.reset();
+        assert_eq!(interpreter.data(), Interpreter::<u8>::new().data());
+        assert_eq!(interpreter.data_pointer(), 0);
+        assert_eq!(interpreter.inst_pointer(), 0);
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_max_steps(Some(1000));
+        let result = run_on(&mut interpreter, "+[]");
+        assert!(matches!(result, Err(BfError::StepLimitReached { limit: 1000 })));
+
+        // a program that finishes within the limit isn't stopped
+        assert!(run_on(&mut interpreter, "+++").is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn timeout() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_timeout(Some(Duration::from_millis(50)));
+        let start = Instant::now();
+        let result = interpreter.run(&compile("+[]").unwrap(), &mut &b""[..], &mut Vec::new());
+        assert!(matches!(result, Err(BfError::Timeout { .. })));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // whichever limit is reached first stops the program
+        interpreter.set_max_steps(Some(100));
+        let result = interpreter.run(&compile("+[]").unwrap(), &mut &b""[..], &mut Vec::new());
+        assert!(matches!(result, Err(BfError::StepLimitReached { limit: 100 })));
+    }
+
+    #[test]
+    fn high_water_mark() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.run(&compile(">>>>").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.high_water_mark(), 4);
+        interpreter.run(&compile("<<<<>>").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.high_water_mark(), 4);
+        interpreter.reset();
+        interpreter.run(&compile(">+[-]").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.high_water_mark(), 1);
+    }
+
+    #[test]
+    fn sixteen_bit_cells() {
+        let program = compile("+".repeat(300)).unwrap();
+        let mut interpreter = Interpreter::<u16>::new();
+        interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.data()[0], 300);
+
+        let mut interpreter = Interpreter::<u8>::new();
+        interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.data()[0], 44);
+    }
+
+    #[test]
+    fn copy_loops() {
+        assert_eq!(compile("[->+<]").unwrap().ops(), [Op::MoveAdd { offset: 1, factor: 1 }, Op::SetZero]);
+        assert_eq!(compile("[-]").unwrap().ops(), [Op::SetZero]);
+        assert_eq!(compile("[+<<--->>]").unwrap().ops(), [Op::MoveAdd { offset: -2, factor: 3 }, Op::SetZero]);
+        // loops doing I/O, moving the data pointer unevenly, or past the cells they change are left
+        // alone
+        assert!(matches!(compile("[->+<.]").unwrap().ops(), [Op::JumpForward(_), ..]));
+        assert!(matches!(compile("[->+<<]").unwrap().ops(), [Op::JumpForward(_), ..]));
+        assert!(matches!(compile("[->+>><<<]").unwrap().ops(), [Op::JumpForward(_), ..]));
+
+        let mut interpreter: Interpreter = Interpreter::new();
+        run_on(&mut interpreter, "+++++[->++<]>[->+>+<<]").unwrap();
+        assert_eq!(&interpreter.data()[..4], [0, 0, 10, 10]);
+    }
+
+    #[test]
+    fn scan_loops_land_on_the_first_zero() {
+        let program = compile("[>]").unwrap();
+        assert_eq!(program.ops(), [Op::ScanZero { step: 1 }]);
+        let mut interpreter: Interpreter = Interpreter::with_size(8);
+        run_on(&mut interpreter, "+>++>+++>>+++++<<<<").unwrap();
+        interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.data_pointer(), 3);
+
+        run_on(&mut interpreter, ">[<]").unwrap();
+        assert_eq!(interpreter.data_pointer(), 3);
+
+        // without a zero, it stops at the end of the array
+        let mut interpreter: Interpreter = Interpreter::with_size(4);
+        run_on(&mut interpreter, "+>+>+>+<<<").unwrap();
+        let result = interpreter.run(&program, &mut &b""[..], &mut Vec::new());
+        assert!(matches!(result, Err(BfError::DataPointerOverflow)));
+    }
+
+    #[test]
+    fn disassembled_jumps_point_at_each_other() {
+        let program = compile("[>.<]").unwrap();
+        let listing: Vec<String> = program.ops().iter().map(ToString::to_string).collect();
+        assert_eq!(listing, ["JumpForward -> 4", "MovePointer 1", "OutputDp", "MovePointer -1", "JumpBackward -> 0"]);
+        assert_eq!(Op::MoveAdd { offset: -1, factor: 2 }.to_string(), "MoveAdd offset=-1 factor=2");
+    }
+
+    #[test]
+    fn nested_loops_jump_to_their_own_brackets() {
+        // the `.`s keep the inner loops from being optimized into scans
+        let program = compile("[>[>.]<[<.]]").unwrap();
+        assert_eq!(program.ops()[0], Op::JumpForward(11));
+        assert_eq!(program.ops()[2], Op::JumpForward(5));
+        assert_eq!(program.ops()[5], Op::JumpBackward(2));
+        assert_eq!(program.ops()[7], Op::JumpForward(10));
+        assert_eq!(program.ops()[10], Op::JumpBackward(7));
+        assert_eq!(program.ops()[11], Op::JumpBackward(0));
+    }
+
+    #[test]
+    fn nested_loops_run() {
+        // 3 * 4 * 5 by counting down nested loops
+        let mut output = Vec::new();
+        execute("+++[>++++[>+++++[>+<-]<-]<-]>>>.", &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, [60]);
+    }
+
+    #[test]
+    fn unbalanced_loops() {
+        assert!(matches!(compile("[[]"), Err(BfError::UnmatchedJump { jump: '[', .. })));
+        assert!(matches!(compile("[]]"), Err(BfError::UnmatchedJump { jump: ']', .. })));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn output_is_flushed_before_input() {
+        use std::cell::RefCell;
+        use std::io::{Read, Write};
+
+        /// Records each write to the log as it reaches it.
+        struct Log<'a>(&'a RefCell<Vec<u8>>);
+
+        impl Write for Log<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        /// Marks each read in the log, and reads an `x`.
+        struct Input<'a>(&'a RefCell<Vec<u8>>);
+
+        impl Read for Input<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().push(b'|');
+                buf[0] = b'x';
+                Ok(1)
+            }
+        }
+
+        let log = RefCell::new(Vec::new());
+        let mut output = std::io::BufWriter::new(Log(&log));
+        let mut input = Input(&log);
+        let program = compile("++++++++[>++++++++<-]>+.+.,.").unwrap();
+        Interpreter::<u8>::new().run(&program, &mut input, &mut output).unwrap();
+        drop(output);
+        assert_eq!(log.into_inner(), b"AB|x");
+    }
+
+    #[test]
+    fn strict_mode() {
+        let strict = CompileOptions { strict: true, ..CompileOptions::default() };
+        let error = compile_with("+a+", &strict).unwrap_err();
+        assert!(matches!(error, BfError::UnexpectedCharacter { byte: b'a', position: SourcePosition { line: 1, column: 2 } }));
+        assert!(compile_with("+ +\n\t+\r\n", &strict).is_ok());
+        assert_eq!(run("+a+.", b"").unwrap(), [2]);
+    }
+
+    #[test]
+    fn closure_input_and_output() {
+        let mut bytes = b"abc".iter().copied();
+        let mut input = InputFn::new(move || bytes.next());
+        let mut written = Vec::new();
+        let mut output = OutputFn::new(|byte| written.push(byte));
+        execute(",[+.,]", &mut input, &mut output).unwrap();
+        assert_eq!(written, b"bcd");
+    }
+
+    #[test]
+    fn endless_loops_that_can_be_entered() {
+        let positions = |code| compile(code).unwrap().endless_loops();
+        assert_eq!(positions("+[]"), [SourcePosition { line: 1, column: 2 }]);
+        assert_eq!(positions("+>+<[>+<]"), [SourcePosition { line: 1, column: 5 }]);
+        assert!(positions("+[-]").is_empty());
+        assert!(positions("+[>]").is_empty());
+        assert!(positions("+[.]").is_empty());
+    }
+
+    #[test]
+    fn endless_loops_that_are_never_entered() {
+        let positions = |code| compile(code).unwrap().endless_loops();
+        assert!(positions("[]+").is_empty());
+        assert!(positions("[ comment [] with [>+<] loops ]+.").is_empty());
+        assert!(positions("+[-][]").is_empty());
+        assert!(positions("+[>][]").is_empty());
+        assert_eq!(positions("[]+[]"), [SourcePosition { line: 1, column: 4 }]);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let program = compile("++>+++<.>[-]").unwrap();
+        let mut interpreter: Interpreter = Interpreter::new();
+        for _ in 0..3 {
+            interpreter.step(&program, &mut &b""[..], &mut Vec::new());
+        }
+        let state = interpreter.snapshot();
+        assert_eq!((state.data_pointer, state.inst_pointer), (1, 3));
+
+        while let StepResult::Continue = interpreter.step(&program, &mut &b""[..], &mut Vec::new()) {}
+        assert_eq!(interpreter.data()[1], 0);
+
+        interpreter.restore(state.clone());
+        assert_eq!(interpreter.snapshot(), state);
+        assert_eq!(interpreter.data()[..2], [2, 3]);
+        assert_eq!(interpreter.data_pointer(), 1);
+        assert_eq!(interpreter.inst_pointer(), 3);
+    }
+
+    #[test]
+    fn step_back_to_the_start() {
+        let program = compile("+++>").unwrap();
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_history(true);
+        let start = interpreter.snapshot();
+        while let StepResult::Continue = interpreter.step(&program, &mut &b""[..], &mut Vec::new()) {}
+        assert_eq!((interpreter.data()[0], interpreter.data_pointer()), (3, 1));
+
+        for (value, data_pointer) in [(3, 0), (0, 0)] {
+            assert!(interpreter.step_back());
+            assert_eq!((interpreter.data()[0], interpreter.data_pointer()), (value, data_pointer));
+        }
+        assert!(!interpreter.step_back());
+        assert_eq!(interpreter.snapshot(), start);
+    }
+
+    #[test]
+    fn step_back_over_input() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_history(true);
+        let program = compile("+,").unwrap();
+        interpreter.step(&program, &mut &b""[..], &mut Vec::new());
+        interpreter.step(&program, &mut &b"x"[..], &mut Vec::new());
+        assert_eq!(interpreter.data()[0], b'x');
+        assert!(interpreter.step_back());
+        assert_eq!(interpreter.data()[0], 1);
+    }
+
+    fn run_cells<T: Cell>(code: &str, output_mode: OutputMode) -> (Vec<u8>, T) {
+        let program = compile(code).unwrap();
+        let mut interpreter = Interpreter::<T>::new();
+        interpreter.set_output_mode(output_mode);
+        let mut output = Vec::new();
+        interpreter.run(&program, &mut &b""[..], &mut output).unwrap();
+        (output, interpreter.data()[0])
+    }
+
+    #[test]
+    fn output_modes() {
+        let code = "++++++++[>++++++++<-]>+.+.";
+        assert_eq!(run_cells::<u8>(code, OutputMode::Char).0, b"AB");
+        assert_eq!(run_cells::<u8>(code, OutputMode::Decimal).0, b"65 66 ");
+        assert_eq!(run_cells::<u8>(code, OutputMode::Hex).0, b"0x41 0x42 ");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn interrupt_flag() {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_interrupt(Some(Arc::clone(&interrupt)));
+
+        let setter = Arc::clone(&interrupt);
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            setter.store(true, Ordering::Relaxed);
+        });
+        let result = interpreter.run(&compile("+[]").unwrap(), &mut &b""[..], &mut Vec::new());
+        thread.join().unwrap();
+        assert!(matches!(result, Err(BfError::Interrupted)));
+
+        // the flag is cleared, so the next run isn't stopped
+        assert!(!interrupt.load(Ordering::Relaxed));
+        assert!(interpreter.run(&compile("+++").unwrap(), &mut &b""[..], &mut Vec::new()).is_ok());
+        assert_eq!(interpreter.data()[0], 4);
+    }
+
+    #[test]
+    fn cells_wrap_at_each_width() {
+        assert_eq!(run_cells::<u8>("-.", OutputMode::Decimal), (b"255 ".to_vec(), u8::MAX));
+        assert_eq!(run_cells::<u16>("-.", OutputMode::Decimal), (b"65535 ".to_vec(), u16::MAX));
+        assert_eq!(run_cells::<u32>("-.", OutputMode::Decimal), (b"4294967295 ".to_vec(), u32::MAX));
+        assert_eq!(run_cells::<u8>("-+", OutputMode::Char).1, 0);
+        assert_eq!(run_cells::<u16>("-+", OutputMode::Char).1, 0);
+        assert_eq!(run_cells::<u32>("-+", OutputMode::Char).1, 0);
+    }
+
+    #[test]
+    fn wide_cells_write_their_low_byte() {
+        // 256 + 65 is past the end of a byte, but not of a wider cell
+        let code = "++++++++++++++++[>++++++++++++++++<-]>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.";
+        assert_eq!(run_cells::<u8>(code, OutputMode::Char).0, b"A");
+        assert_eq!(run_cells::<u16>(code, OutputMode::Char).0, b"A");
+        assert_eq!(run_cells::<u16>(code, OutputMode::Decimal).0, b"321 ");
+        assert_eq!(run_cells::<u32>(code, OutputMode::Decimal).0, b"321 ");
+    }
+
+    /// Run `code` on a wrapping Interpreter with `size` cells, returning the high water mark and
+    /// the memory cells.
+    fn run_wrapping(code: &str, size: usize) -> (usize, Vec<u8>) {
+        let mut interpreter: Interpreter = Interpreter::with_size(size);
+        interpreter.set_wrapping(true);
+        interpreter.run(&compile(code).unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        (interpreter.high_water_mark(), interpreter.data().to_vec())
+    }
+
+    #[test]
+    fn wrapping_raises_the_high_water_mark_to_the_last_cell() {
+        assert_eq!(run_wrapping("<+", 12).0, 11);
+        assert_eq!(run_wrapping(">>+", 12).0, 2);
+        // a run of moves wraps all at once, but still passes the last cell on the way
+        assert_eq!(run_wrapping("<<<+", 12).0, 11);
+        let (high_water_mark, data) = run_wrapping(">>>>>>>>>>>>+", 12);
+        assert_eq!((high_water_mark, data[0]), (11, 1));
+    }
+
+    #[test]
+    fn debug_shows_a_window_of_the_tape() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.run(&compile(format!("+>++{}+++", ">".repeat(21))).unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        let debug = format!("{interpreter:?}");
+        assert!(debug.starts_with("Interpreter { data_pointer: 22, inst_pointer: 5, cells: 30000, tape: ... "));
+        assert!(debug.contains(" 0 [3] 0 "));
+        assert!(debug.ends_with(" ... }"));
+        assert_eq!(debug.split_whitespace().filter(|cell| cell.parse::<u8>().is_ok()).count(), DEBUG_WINDOW - 1);
+
+        let fresh: Interpreter = Interpreter::with_size(3);
+        assert_eq!(format!("{fresh:?}"), "Interpreter { data_pointer: 0, inst_pointer: 0, cells: 3, tape: [0] 0 0 }");
+    }
+
+    #[test]
+    fn instructions_executed() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.run(&compile("+.+.").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.instructions_executed(), 4);
+        // a combined Op counts once
+        interpreter.run(&compile("+++.").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.instructions_executed(), 2);
+    }
+
+    #[test]
+    fn multibyte_input_is_read_a_byte_at_a_time() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        let mut input = "é".as_bytes();
+        interpreter.run(&compile(",>,>,").unwrap(), &mut input, &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.data()[..3], [0xc3, 0xa9, 0]);
+        assert_eq!(run(",[.,]", "héllo".as_bytes()).unwrap(), "héllo".as_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tee_writes_to_both_outputs() {
+        let (mut first, mut second) = (Vec::new(), std::io::BufWriter::new(Vec::new()));
+        let mut output = Tee::new(&mut first, &mut second);
+        execute("++++++++[>++++++++<-]>+.+.[-]++++++++++.", &mut &b""[..], &mut output).unwrap();
+        assert_eq!(first, b"AB\n");
+        // the buffered output was flushed when the program finished
+        assert_eq!(second.get_ref(), &first);
+    }
+
+    #[test]
+    fn cell_access() {
+        let mut interpreter: Interpreter = Interpreter::with_size(4);
+        interpreter.set_cell(3, 9).unwrap();
+        assert_eq!(interpreter.cell(3), Some(9));
+        assert_eq!(interpreter.cell(0), Some(0));
+        assert_eq!(interpreter.cell(4), None);
+        assert!(matches!(interpreter.set_cell(4, 1), Err(BfError::CellOutOfRange { index: 4, cells: 4 })));
+
+        interpreter.run(&compile(">>>.").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(interpreter.data_pointer(), 3);
+    }
+
+    #[test]
+    fn programs_without_commands() {
+        for code in ["", " \n\t ", "just a comment"] {
+            let program = compile(code).unwrap();
+            assert!(program.ops().is_empty());
+            assert_eq!(run(code, b"").unwrap(), b"");
+        }
+        assert!(unmatched_jumps("", Commands::default()).is_empty());
+        // a comment loop is skipped, since every cell starts at 0
+        assert_eq!(run("[+-.,<>] comment loop", b"").unwrap(), b"");
+    }
+
+    fn program(ops: Vec<Op>) -> Program {
+        let positions = vec![SourcePosition { line: 1, column: 1 }; ops.len()];
+        Program { ops, positions }
+    }
+
+    #[test]
+    fn pointer_bounds_of_balanced_loops() {
+        let program = compile("+[->>+<<]<<+>").unwrap();
+        assert_eq!(program.pointer_bounds(), Some((-2, 2)));
+        assert_eq!(compile("+[>]").unwrap().pointer_bounds(), None);
+        assert_eq!(compile("+[->+<]>[<]").unwrap().pointer_bounds(), None);
+    }
+
+    #[test]
+    fn pointer_bounds_of_overlapping_loops() {
+        let crossing = program(vec![Op::JumpForward(2), Op::JumpForward(3), Op::JumpBackward(0), Op::JumpBackward(1)]);
+        assert_eq!(crossing.pointer_bounds(), None);
+        let unclosed = program(vec![Op::JumpForward(1), Op::JumpBackward(0), Op::JumpForward(0)]);
+        assert_eq!(unclosed.pointer_bounds(), None);
+    }
+
+    #[test]
+    fn unchecked_runs_in_bounds_program() {
+        let code = "++++++++[>++++++++<-]>+.+.<+++[>>+<<-]>>.";
+        let program = compile(code).unwrap();
+        assert_eq!(program.pointer_bounds(), Some((0, 2)));
+        let mut interpreter: Interpreter = Interpreter::with_size(3);
+        interpreter.set_unchecked(true);
+        let mut output = Vec::new();
+        interpreter.run(&program, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, run(code, b"").unwrap());
+    }
+
+    #[test]
+    fn unchecked_falls_back_to_checked_access() {
+        let program = compile("+[>+]").unwrap();
+        let mut interpreter: Interpreter = Interpreter::with_size(4);
+        interpreter.set_unchecked(true);
+        let result = interpreter.run(&program, &mut &b""[..], &mut Vec::new());
+        assert!(matches!(result, Err(BfError::DataPointerOverflow)));
+
+        // the bounds fit from the start, but not from a data pointer nearer the end
+        let program = compile(">>+").unwrap();
+        let mut interpreter: Interpreter = Interpreter::with_size(4);
+        interpreter.set_unchecked(true);
+        interpreter.run(&compile(">>").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        let result = interpreter.run(&program, &mut &b""[..], &mut Vec::new());
+        assert!(matches!(result, Err(BfError::DataPointerOverflow)));
+    }
+
+    #[test]
+    fn iterating_collects_the_ops() {
+        let options = CompileOptions { opt_level: OptLevel::O0, ..CompileOptions::default() };
+        let ops: Vec<Op> = compile_with("++.", &options).unwrap().iter().cloned().collect();
+        assert_eq!(ops, [Op::IncrementDpValue, Op::IncrementDpValue, Op::OutputDp]);
+        let program = compile("++.").unwrap();
+        assert_eq!(program.iter().collect::<Vec<_>>(), program.ops().iter().collect::<Vec<_>>());
+        assert_eq!((&program).into_iter().count(), program.ops().len());
+    }
+
+    #[test]
+    fn custom_commands() {
+        let commands = Commands::new("RLIDOGnx").unwrap();
+        let options = CompileOptions { commands, ..CompileOptions::default() };
+        let program = compile_with("IIIIIIn RIIIIIIIIIILDx RO", &options).unwrap();
+        let mut output = Vec::new();
+        Interpreter::<u8>::new().run(&program, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, b"<");
+        // the usual commands are now comments
+        assert!(compile_with("+++.", &options).unwrap().ops().is_empty());
+
+        assert!(Commands::new("><+-.,[").is_none());
+        assert!(Commands::new("><+-.,[]]").is_none());
+        assert!(Commands::new("><+-.,[[").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn buffered_output_matches_unbuffered() {
+        // writes every byte value but 0, then reads and writes after them
+        let program = compile("+[[>+>+<<-]>[<+>-]>.[-]<<+],.,.").unwrap();
+        let mut unbuffered = Vec::new();
+        let mut output = OutputFn::new(|byte| unbuffered.push(byte));
+        Interpreter::<u8>::new().run(&program, &mut &b"ab"[..], &mut output).unwrap();
+
+        let mut buffered = std::io::BufWriter::with_capacity(7, Vec::new());
+        Interpreter::<u8>::new().run(&program, &mut &b"ab"[..], &mut buffered).unwrap();
+        let buffered = buffered.into_inner().unwrap();
+        assert_eq!(buffered.len(), 257);
+        assert_eq!(buffered, unbuffered);
+    }
+
+    #[test]
+    fn run_returns_bytes_written() {
+        let program = compile("++++++++[>+++++++++<-]>.+++++++++++++++++++++++++++++++++.").unwrap();
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::<u8>::new();
+        let written = interpreter.run(&program, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(written, interpreter.bytes_written());
+        assert_eq!(output, b"Hi");
+        assert_eq!(written, 2);
+        assert_eq!(execute("+++++[>+++++<-]>.", &mut &b""[..], &mut Vec::new()).unwrap(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn normalize_newlines() {
+        use std::io::Read;
+
+        let mut normalized = Vec::new();
+        NormalizeNewlines::new(&b"a\r\nb"[..]).read_to_end(&mut normalized).unwrap();
+        assert_eq!(normalized, b"a\nb");
+        normalized.clear();
+        NormalizeNewlines::new(&b"\ra\n\r\r\n\n"[..]).read_to_end(&mut normalized).unwrap();
+        assert_eq!(normalized, b"\na\n\n\n\n");
+
+        let program = compile(",[.,]").unwrap();
+        let mut output = Vec::new();
+        let mut input = NormalizeNewlines::new(&b"a\r\nb\r"[..]);
+        Interpreter::<u8>::new().run(&program, &mut input, &mut output).unwrap();
+        assert_eq!(output, b"a\nb\n");
+    }
+
+    #[test]
+    fn run_on_a_slice() {
+        let mut tape = [0u8; 8];
+        tape[7] = 5;
+        let mut interpreter: Interpreter = Interpreter::new();
+        let program = compile("+>++>+++>>>>>[-<<<<<<<+>>>>>>>]").unwrap();
+        interpreter.run_on(&program, &mut tape, &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(tape, [6, 2, 3, 0, 0, 0, 0, 0]);
+        assert_eq!(interpreter.data_pointer(), 0);
+        assert!(interpreter.data().iter().all(|&cell| cell == 0));
+    }
+
+    #[test]
+    fn run_on_keeps_the_tape_after_an_error() {
+        let mut tape = [0u8; 8];
+        let mut interpreter: Interpreter = Interpreter::new();
+        let result = interpreter.run_on(&compile("+[>+]").unwrap(), &mut tape, &mut &b""[..], &mut Vec::new());
+        assert!(matches!(result, Err(BfError::DataPointerOverflow)));
+        assert_eq!(tape, [1; 8]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn run_on_keeps_the_tape_after_a_panic() {
+        let mut tape = [0u8; 8];
+        let mut interpreter: Interpreter = Interpreter::new();
+        let program = compile("+>++.").unwrap();
+        let mut output = OutputFn::new(|_| panic!("the output failed"));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            interpreter.run_on(&program, &mut tape, &mut &b""[..], &mut output)
+        }));
+        assert!(result.is_err());
+        assert_eq!(tape, [1, 2, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(interpreter.data().len(), DATA_SIZE);
+    }
+
+    #[test]
+    fn guard_shows_the_ops_before_a_pointer_error() {
+        let options = CompileOptions { opt_level: OptLevel::O0, ..CompileOptions::default() };
+        let program = compile_with("+>+<<", &options).unwrap();
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_guard(true);
+        let error = interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap_err();
+        let BfError::Guarded { error: cause, op, position, recent } = &error else {
+            panic!("{error:?} should be guarded");
+        };
+        assert!(matches!(**cause, BfError::DataPointerUnderflow));
+        assert_eq!((*op, *position), (Op::DecrementDp, SourcePosition { line: 1, column: 5 }));
+        assert_eq!(recent[..], [Op::IncrementDpValue, Op::IncrementDp, Op::IncrementDpValue, Op::DecrementDp]);
+        assert!(error.to_string().starts_with("Cannot decrement data pointer below 0."));
+        assert!(error.to_string().contains("line 1, column 5, after IncrementDpValue, IncrementDp"));
+
+        // only the last few Ops are kept
+        let program = compile_with(format!("{}<", "+-".repeat(20)), &options).unwrap();
+        let error = interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(error, BfError::Guarded { recent, .. } if recent.len() == GUARD_HISTORY));
+
+        interpreter.set_guard(false);
+        let error = interpreter.run(&program, &mut &b""[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(error, BfError::DataPointerUnderflow));
+    }
+
+    fn run_test_ops(code: &str) -> Result<usize, BfError> {
+        let program = compile_with(code, &CompileOptions { test_ops: true, ..CompileOptions::default() })?;
+        Interpreter::<u8>::new().run(&program, &mut &b""[..], &mut Vec::new())
+    }
+
+    #[test]
+    fn assertions_pass() {
+        assert!(run_test_ops("+?").is_ok());
+        assert!(run_test_ops("=+?-=").is_ok());
+        // without --test-ops they are comments
+        assert!(run("?", b"").is_ok());
+    }
+
+    #[test]
+    fn assertions_fail() {
+        assert!(matches!(
+            run_test_ops("?"),
+            Err(BfError::AssertionFailed { expected_zero: false, index: 0, value: 0 })
+        ));
+        assert!(matches!(
+            run_test_ops(">++="),
+            Err(BfError::AssertionFailed { expected_zero: true, index: 1, value: 2 })
+        ));
+    }
+
+    #[test]
+    fn input_limit_acts_as_the_end_of_input() {
+        let program = compile(",.,.,.,.").unwrap();
+        for (eof_mode, third) in [(EofMode::Zero, 0), (EofMode::NegativeOne, 255), (EofMode::Unchanged, b'b')] {
+            let mut interpreter: Interpreter = Interpreter::new();
+            interpreter.set_input_limit(Some(2));
+            interpreter.set_eof_mode(eof_mode);
+            let mut input = &b"abcd"[..];
+            let mut output = Vec::new();
+            interpreter.run(&program, &mut input, &mut output).unwrap();
+            assert_eq!(output, [b'a', b'b', third, third]);
+            // the rest of the input is left unread
+            assert_eq!(input, b"cd");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn events_for_each_change() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut interpreter: Interpreter = Interpreter::new();
+        let sink = Arc::clone(&events);
+        interpreter.set_on_event(Some(Box::new(move |event| sink.lock().unwrap().push(event))));
+        interpreter.run(&compile("+>").unwrap(), &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(*events.lock().unwrap(), [
+            BfEvent::CellChanged { index: 0, old: 0, new: 1 },
+            BfEvent::PointerMoved { from: 0, to: 1 },
+        ]);
+    }
+
+    #[test]
+    fn interpreter_with_events_is_send() {
+        fn assert_send<S: Send>(_: &S) {}
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.set_on_event(Some(Box::new(|_| ())));
+        assert_send(&interpreter);
+    }
+}
@@ -1,379 +1,1853 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::{env, io::{stdin, stdout, Write}, process};
-
-use console::Term;  // read_char()
-
-/// The size of the array of memory cells used by brainfuck.
-/// This can be changed and recompiled to suit different needs.
-/// Having this as a `const` allows us to use it as a const generic
-/// and stack allocate the `Interpreter` struct.
-const DATA_SIZE: usize          = 30000;
-
-/// The 6 characters that will be interpreted as brainfuck code.
-
-/// `>` increments the position of the data pointer by 1.
-/// Incrementing the data pointer above `DATA_SIZE` results in a panic.
-const INCREMENT_DP: char        = '>';
-/// `<` decrements the position of the data pointer by 1.
-/// Decrementing the data pointer below 0 results in a panic.
-const DECREMENT_DP: char        = '<';
-/// `+` increments the value of the byte at the data pointer by 1.
-/// Incrementing a byte over `i8::MAX`, or 127, results in the value wrapping around to `i8::MIN`,
-/// or -128.
-const INCREMENT_DP_VALUE: char  = '+';
-/// `-` decrements the value of the byte at the data pointer by 1.
-/// Decrementing a byte below 0 results in the value wrapping around to `i8::MAX`,
-/// or 127.
-const DECREMENT_DP_VALUE: char  = '-';
-/// `.` outputs the byte at the data pointer to the console.
-const OUTPUT_DP: char           = '.';
-/// `,` prompts the user to input a single character, which is written to the byte at the data
-/// pointer.
-const INPUT_DP: char            = ',';
-/// `[` moves the instruction pointer forwards to the command after the matching `]` if the byte at the data
-/// pointer is 0, or else the instruction pointer is incremented by 1.
-const JUMP_FORWARD: char        = '[';
-/// `]` moves the instruction pointer backwards to the command after the matching `[` if the byte
-/// at the data pointer is non-zero, or else the instruction pointer is incremented by 1.
-const JUMP_BACK: char           = ']';
+use std::fmt;
+use std::fs::File;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::{env, io::{stdin, stdout, BufWriter, IsTerminal, Read, Write}, process, time::{Duration, Instant}};
+
+use brainfuck::{BfError, Cell, Commands, CompileOptions, EofMode, Interpreter, NormalizeNewlines, Op, OptLevel, OutputMode, Program, Sink, StepResult, Tee, TermInput, DATA_SIZE, MAX_DEPTH};
+use console::{Key, Term};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    match args.len() {
-        1 => repl(false),
-        2|3|4 => {
-            if args.len() == 2 && args[1] == "-c" {
-                repl(true);
+    let mut options = match parse_args(&args[1..]) {
+        Ok(Command::Run(options)) => *options,
+        Ok(Command::Help) => {
+            usage();
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("brainfuck {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error: {e}.");
+            usage();
+            return;
+        }
+    };
+
+    if let Some(ref path) = options.init_file {
+        match std::fs::read(path) {
+            Ok(bytes) => options.init = bytes,
+            Err(e) => {
+                eprintln!("Error reading file: {e}");
                 return;
             }
+        }
+    }
 
-            if !args[1].ends_with(".bf") {
-                eprintln!("Error: file {} was not a `.bf` file.", args[1]);
+    if let Some(ref path) = options.input_file {
+        match std::fs::read(path) {
+            Ok(bytes) => options.input = Some(bytes),
+            Err(e) => {
+                eprintln!("Error reading file: {e}");
                 return;
             }
+        }
+    }
 
-            if args.len() >= 3 && (args[2] != "-v" && args[2] != "-c") {
-                usage();
+    // the file is created once, so that each line run by the REPL adds to it
+    if let Some(ref path) = options.out_path {
+        match File::create(path) {
+            Ok(file) => options.out = Some(file),
+            Err(e) => {
+                eprintln!("Error creating file: {e}");
                 return;
             }
+        }
+    }
+
+    if options.visual && !Term::stderr().is_term() {
+        eprintln!("Error: --visual needs a terminal to draw on.");
+        return;
+    }
+
+    if options.cells == 0 {
+        eprintln!("Error: the number of cells must be at least 1.");
+        return;
+    }
+
+    if options.init.len() > options.cells {
+        eprintln!("Error: there are {} bytes to load but only {} memory cells.", options.init.len(), options.cells);
+        return;
+    }
+
+    // any bytes from `--init` are loaded over the filled cells
+    if let Some(Fill::Random(seed)) = options.fill {
+        let seed = seed.unwrap_or_else(|| {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64);
+            // the run can only be repeated if the seed is known
+            eprintln!("Filling the memory cells with seed {seed}.");
+            seed
+        });
+        let mut random = SplitMix64(seed);
+        let mut bytes: Vec<u8> = (0..options.cells).map(|_| random.next() as u8).collect();
+        bytes[..options.init.len()].copy_from_slice(&options.init);
+        options.init = bytes;
+    }
+
+    if let Some((start, len)) = options.dump_window {
+        // with `--grow` there may be more cells by the time they are dumped
+        if !options.grow && start + len > options.cells {
+            eprintln!("Error: the dump window {start}:{len} is past the end of the {} memory cells.", options.cells);
+            return;
+        }
+    }
 
-            if args.len() == 4 && (args[3] != "-v" && args[3] != "-c") {
-                usage();
+    match options.cell_size {
+        8 => start::<u8>(&options),
+        16 => start::<u16>(&options),
+        _ => start::<u32>(&options),
+    }
+}
+
+/// Parse a string of hexadecimal digits, two to each byte, such as `0a1B`.
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(&hex[start..start + 2], 16).ok())
+        .collect()
+}
+
+/// Run the REPL, a file or the code given, with memory cells of type `T`.
+fn start<T: Cell>(options: &Options) {
+    match (&options.file, &options.code) {
+        (None, None) if options.stdin || !stdin().is_terminal() => {
+            let mut code = Vec::new();
+            match stdin().read_to_end(&mut code) {
+                Ok(_) => run_code::<T>(&code, options),
+                Err(e) => eprintln!("Error reading stdin: {e}"),
+            }
+        }
+        (None, None) => repl::<T>(options),
+        (None, Some(code)) => {
+            run_code::<T>(code.as_bytes(), options);
+        }
+        (Some(_), Some(_)) => usage(),
+        (Some(file), None) => {
+            // Ook! files don't have an extension of their own
+            if !options.from_ook && !file.ends_with(".bf") && !file.ends_with(".bfc") {
+                eprintln!("Error: file {file} was not a `.bf` or `.bfc` file.");
+                check_failed(options);
                 return;
             }
 
-            let verbose = (args.len() == 3 && args[2] == "-v") || (args.len() == 4 && (args[3] == "-v" || args[2] == "-v"));
-            let display_chars = (args.len() == 3 && args[2] == "-c") || (args.len() == 4 && (args[3] == "-c" || args[2] == "-c"));
-
-            let result = run_file(&args[1], verbose, display_chars);
+            let result = run_file::<T>(file, options);
             if result.is_err() {
                 eprintln!("Error reading file: {}", result.err().unwrap());
+                check_failed(options);
             }
         }
-        _ => usage(),
     }
 }
 
+/// The languages that a program can be translated into.
+#[derive(Clone, Copy)]
+enum Language {
+    C,
+    Rust,
+}
+
+/// How the memory cells are filled before the program starts.
+#[derive(Clone, Copy)]
+enum Fill {
+    /// Pseudo-random bytes from the given seed, or from a seed taken from the clock.
+    Random(Option<u64>),
+}
+
+/// SplitMix64, a small pseudo-random number generator, so that the same seed always gives the
+/// same numbers on every platform.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// The ways code can be rewritten rather than run.
+#[derive(Clone, Copy)]
+enum Rewrite {
+    Format,
+    Minify,
+    /// Minify, also removing commands that cancel each other out.
+    MinifyAggressive,
+}
+
+/// The options given on the command line.
+struct Options {
+    file: Option<String>,
+    code: Option<String>,
+    stdin: bool,
+    verbose: bool,
+    eof_mode: EofMode,
+    output_mode: OutputMode,
+    cells: usize,
+    cell_size: u32,
+    grow: bool,
+    wrap_pointer: bool,
+    unsafe_fast: bool,
+    init: Vec<u8>,
+    init_file: Option<String>,
+    fill: Option<Fill>,
+    input: Option<Vec<u8>>,
+    input_file: Option<String>,
+    trace: bool,
+    watches: Vec<usize>,
+    guard: bool,
+    debug: bool,
+    breaks: Vec<Condition>,
+    test_ops: bool,
+    strict: bool,
+    auto_close: bool,
+    max_depth: usize,
+    opt_level: OptLevel,
+    no_bang: bool,
+    echo: bool,
+    normalize_newlines: bool,
+    from_ook: bool,
+    commands: Commands,
+    bench: Option<usize>,
+    profile: bool,
+    dump: bool,
+    dump_window: Option<(usize, usize)>,
+    ascii_art: bool,
+    dump_json: bool,
+    histogram: bool,
+    visual: bool,
+    diff: Option<String>,
+    quiet: bool,
+    rewrite: Option<Rewrite>,
+    disasm: bool,
+    decompile: bool,
+    cfg_dot: bool,
+    stats: bool,
+    opt_report: bool,
+    check: bool,
+    emit_bytecode: Option<String>,
+    max_steps: Option<u64>,
+    input_limit: Option<u64>,
+    timeout: Option<Duration>,
+    emit: Option<Language>,
+    output: Option<String>,
+    out: Option<File>,
+    out_path: Option<String>,
+}
+
+/// What the command line arguments ask for.
+enum Command {
+    Run(Box<Options>),
+    Help,
+    Version,
+}
+
+/// Parse the command line arguments, not including the program name.
+/// Returns an error describing the problem if the arguments are invalid, in which case the usage
+/// should be shown.
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut options = Options {
+        file: None,
+        code: None,
+        stdin: false,
+        verbose: false,
+        eof_mode: EofMode::Zero,
+        output_mode: OutputMode::Char,
+        cells: DATA_SIZE,
+        cell_size: 8,
+        grow: false,
+        wrap_pointer: false,
+        unsafe_fast: false,
+        init: Vec::new(),
+        init_file: None,
+        fill: None,
+        input: None,
+        input_file: None,
+        trace: false,
+        watches: Vec::new(),
+        guard: false,
+        debug: false,
+        breaks: Vec::new(),
+        test_ops: false,
+        strict: false,
+        auto_close: false,
+        max_depth: MAX_DEPTH,
+        opt_level: OptLevel::O2,
+        no_bang: false,
+        echo: true,
+        normalize_newlines: false,
+        from_ook: false,
+        commands: Commands::default(),
+        bench: None,
+        profile: false,
+        dump: false,
+        dump_window: None,
+        ascii_art: false,
+        dump_json: false,
+        histogram: false,
+        visual: false,
+        diff: None,
+        quiet: false,
+        rewrite: None,
+        disasm: false,
+        decompile: false,
+        cfg_dot: false,
+        stats: false,
+        opt_report: false,
+        check: false,
+        emit_bytecode: None,
+        max_steps: None,
+        input_limit: None,
+        timeout: None,
+        emit: None,
+        output: None,
+        out: None,
+        out_path: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // the value given after the option, which is an error if there isn't one
+        let mut value = || args.next().map(String::as_str).ok_or_else(|| format!("{arg} needs a value"));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--version" => return Ok(Command::Version),
+            "-v" | "--verbose" => options.verbose = true,
+            "-e" | "--code" => options.code = Some(value()?.to_string()),
+            // `-c` chose characters over numbers before they became the default, and still works
+            "-c" => options.output_mode = OutputMode::Char,
+            "--stdin" => options.stdin = true,
+            "--eof" => {
+                options.eof_mode = match value()? {
+                    "unchanged" => EofMode::Unchanged,
+                    "zero" => EofMode::Zero,
+                    "negative-one" => EofMode::NegativeOne,
+                    other => return Err(invalid(arg, other)),
+                };
+            }
+            "--output-mode" => {
+                options.output_mode = match value()? {
+                    "char" => OutputMode::Char,
+                    "decimal" => OutputMode::Decimal,
+                    "hex" => OutputMode::Hex,
+                    other => return Err(invalid(arg, other)),
+                };
+            }
+            "--cells" => options.cells = parse_value(arg, value()?)?,
+            "--cell-size" => {
+                options.cell_size = match value()? {
+                    size @ ("8" | "16" | "32") => parse_value(arg, size)?,
+                    other => return Err(invalid(arg, other)),
+                };
+            }
+            "--grow" => options.grow = true,
+            "--wrap-pointer" => options.wrap_pointer = true,
+            "--unsafe-fast" => options.unsafe_fast = true,
+            "--init" => {
+                let hex = value()?;
+                options.init = parse_hex(hex).ok_or_else(|| invalid(arg, hex))?;
+            }
+            "--init-file" => options.init_file = Some(value()?.to_string()),
+            "--fill" => {
+                options.fill = match value()? {
+                    "random" => Some(Fill::Random(None)),
+                    other => match other.strip_prefix("random:") {
+                        Some(seed) => Some(Fill::Random(Some(parse_value(arg, seed)?))),
+                        None => return Err(invalid(arg, other)),
+                    },
+                };
+            }
+            "--input" => options.input_file = Some(value()?.to_string()),
+            "--out" => options.out_path = Some(value()?.to_string()),
+            "--trace" => options.trace = true,
+            "--watch" => options.watches.push(parse_value(arg, value()?)?),
+            "--guard" => options.guard = true,
+            "--debug" => options.debug = true,
+            "--visual" => options.visual = true,
+            "--break" => {
+                let condition = value()?;
+                options.breaks.push(Condition::parse(condition).ok_or_else(|| invalid(arg, condition))?);
+                options.debug = true;
+            }
+            "--test-ops" => options.test_ops = true,
+            "--strict" => options.strict = true,
+            "--auto-close" => options.auto_close = true,
+            "--max-depth" => options.max_depth = parse_value(arg, value()?)?,
+            "-O0" => options.opt_level = OptLevel::O0,
+            "-O1" => options.opt_level = OptLevel::O1,
+            "-O2" => options.opt_level = OptLevel::O2,
+            "-O3" => options.opt_level = OptLevel::O3,
+            "--no-bang" => options.no_bang = true,
+            "--echo" => options.echo = true,
+            "--no-echo" => options.echo = false,
+            "--normalize-newlines" => options.normalize_newlines = true,
+            "--from-ook" => options.from_ook = true,
+            "--map" => {
+                let chars = value()?;
+                options.commands = Commands::new(chars)
+                    .ok_or_else(|| format!("{arg} needs 8 different characters, one for each of ><+-.,[], not {chars}"))?;
+            }
+            "--bench" => {
+                let runs = value()?;
+                match parse_value(arg, runs)? {
+                    0 => return Err(invalid(arg, runs)),
+                    runs => options.bench = Some(runs),
+                }
+            }
+            "--profile" => options.profile = true,
+            "--dump" => options.dump = true,
+            "--dump-window" => {
+                let window = value()?;
+                let (start, len) = window.split_once(':').ok_or_else(|| invalid(arg, window))?;
+                let len: usize = parse_value(arg, len)?;
+                if len == 0 {
+                    return Err(invalid(arg, window));
+                }
+                options.dump_window = Some((parse_value(arg, start)?, len));
+                options.dump = true;
+            }
+            "--ascii-art" => {
+                options.ascii_art = true;
+                options.dump = true;
+            }
+            "--dump-json" => options.dump_json = true,
+            "--histogram" => options.histogram = true,
+            "--diff" => options.diff = Some(value()?.to_string()),
+            "--quiet" => options.quiet = true,
+            "--format" => options.rewrite = Some(Rewrite::Format),
+            "--minify" => options.rewrite = Some(Rewrite::Minify),
+            "--minify-aggressive" => options.rewrite = Some(Rewrite::MinifyAggressive),
+            "--disasm" => options.disasm = true,
+            "--decompile" => options.decompile = true,
+            "--cfg-dot" => options.cfg_dot = true,
+            "--stats" => options.stats = true,
+            "--opt-report" => options.opt_report = true,
+            "--check" => options.check = true,
+            "--max-steps" => options.max_steps = Some(parse_value(arg, value()?)?),
+            "--input-limit" => options.input_limit = Some(parse_value(arg, value()?)?),
+            "--timeout" => options.timeout = Some(Duration::from_millis(parse_value(arg, value()?)?)),
+            "--emit-c" => options.emit = Some(Language::C),
+            "--emit-rust" => options.emit = Some(Language::Rust),
+            "--emit-bc" => options.emit_bytecode = Some(value()?.to_string()),
+            "-o" | "--output" => options.output = Some(value()?.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ if options.file.is_none() => options.file = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {arg}, as only one file can be run")),
+        }
+    }
+
+    Ok(Command::Run(Box::new(options)))
+}
+
+/// The error for an invalid `value` given to the option `arg`.
+fn invalid(arg: &str, value: &str) -> String {
+    format!("invalid value {value} for {arg}")
+}
+
+/// Parse the `value` given to the option `arg`.
+fn parse_value<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| invalid(arg, value))
+}
+
 /// Run the REPL.
 /// Creates an instance of the Interpreter struct, and continually prompts the user to input a
-/// line which is compiled and ran. 'exit' can be entered to exit the REPL.
-fn repl(display_chars: bool) {
+/// line which is compiled and ran. If a line leaves a `[` unclosed, more lines are read until it
+/// is closed, or until an empty line abandons it.
+/// 'exit' can be entered to exit the REPL, ':mem' and ':ptr' show the state of the memory cells and
+/// the data pointer, ':reset' clears them, and ':load file' runs a file on them. Ctrl-D on an
+/// empty line also exits, and the up and down arrows go through the lines entered before, which
+/// are kept in the `History`.
+fn repl<T: Cell>(options: &Options) {
     println!("Welcome to brainfuck!");
-    let mut interpreter = Interpreter::<DATA_SIZE>::new(); 
+    println!("Memory and the data pointer carry over from one line to the next, enter :reset to clear them.");
+    let mut interpreter = interpreter::<T>(options);
+    interpreter.set_interrupt(Some(handle_interrupts()));
+
+    let term = Term::stdout();
+    let mut history = History::load();
+
+    // Lines are collected here until every `[` has been closed
+    let mut code = String::new();
     loop {
-        println!();
-        print!("> ");
-        stdout().flush().unwrap();
-
-        let mut buffer = String::new();
-        match stdin().read_line(&mut buffer) {
-            Ok(_) => {
-                match buffer.trim() {
-                    "exit" => process::exit(0),
-                    _ => interpreter.compile(buffer, false, display_chars),
+        let prompt = if code.is_empty() {
+            println!();
+            "> "
+        } else {
+            "... "
+        };
+
+        let buffer = match read_line(&term, prompt, &history) {
+            Ok(Some(line)) => line,
+            // Ctrl-D on an empty line exits, like `exit`
+            Ok(None) => process::exit(0),
+            Err(error) => {
+                eprintln!("Error: {error}");
+                return;
+            }
+        };
+        history.add(&buffer);
+
+        if code.is_empty() {
+            match buffer.trim() {
+                "exit" => process::exit(0),
+                // there is nothing to run, so just prompt again
+                "" => continue,
+                ":mem" => {
+                    dump_memory(&interpreter);
+                    continue;
+                }
+                ":ptr" => {
+                    println!("{}", interpreter.data_pointer());
+                    continue;
+                }
+                ":reset" => {
+                    interpreter.reset();
+                    continue;
+                }
+                ":load" => {
+                    println!("Usage: :load file.bf");
+                    continue;
                 }
+                line if line.starts_with(":load ") => {
+                    RUNNING.store(true, Ordering::Relaxed);
+                    if let Err(e) = load(&mut interpreter, line[":load ".len()..].trim(), options) {
+                        eprintln!("{e}");
+                    }
+                    RUNNING.store(false, Ordering::Relaxed);
+                    continue;
+                }
+                _ => (),
             }
-            Err(error) => println!("Error: {error}"),
+        } else {
+            // an empty line abandons an unfinished program
+            match buffer.trim() {
+                "exit" => process::exit(0),
+                "" => {
+                    code.clear();
+                    continue;
+                }
+                _ => (),
+            }
+        }
+
+        code.push_str(&buffer);
+        code.push('\n');
+        if open_jumps(&code, options.commands) > 0 {
+            continue;
+        }
+
+        RUNNING.store(true, Ordering::Relaxed);
+        if let Err(e) = compile_and_run(&mut interpreter, code.as_bytes(), options) {
+            eprintln!("{e}");
         }
+        RUNNING.store(false, Ordering::Relaxed);
+        code.clear();
     }
 }
 
-/// Read the given file, create and instance of the Interpreter struct and run the file.
-/// Path given to this function has already been checked to be a `.bf` file, and any errors
-/// encountered while reading the file are reported.
-fn run_file(file_path: &String, verbose: bool, display_chars: bool) -> Result<(), Box<dyn Error>> {
-    let text = std::fs::read_to_string(file_path)?.parse()?;
-    let mut interpreter = Interpreter::<DATA_SIZE>::new();
-    interpreter.compile(text, verbose, display_chars);
-    Ok(())
-}
+/// The number of lines kept in the REPL's history.
+const HISTORY_SIZE: usize = 1000;
 
-fn usage() {
-    println!("Brainfuck\n\
-        \n\
-        Usage:\n\
-        \n\
-        brainfuck [file [-v] [-c]]\n\
-        "
-        );
+/// The lines entered into the REPL, saved to `~/.brainfuck_history` so that they carry over from
+/// one session to the next.
+struct History {
+    lines: Vec<String>,
+    path: Option<PathBuf>,
 }
 
-/// An enum to represent the 6 operations within brainfuck.
-/// Any brainfuck program is compiled into a list of Ops, as a lightweight way to run through the
-/// operations of the program.
-#[derive(PartialEq)]
-enum Op {
-    IncrementDp,
-    DecrementDp,
-    IncrementDpValue,
-    DecrementDpValue,
-    OutputDp,
-    InputDp,
-    JumpForward,
-    JumpBackward,
-}
-
-/// A small struct to hold the start and end positions (within the list of operations) for matching
-/// `[` and `]` pairs.
-struct JumpPosition {
-    start: usize,
-    end: usize,
-}
-
-/// The Interpreter struct holds the array of memory cells, the data and instruction pointers, and
-/// Vecs of Ops and JumpInstructions that are filled during compilation.
-/// Using a const generic allows us to stack allocate the Interpreter while experimenting with
-/// different sizes of the data array.
-struct Interpreter<const N: usize> {
-    data: [i8; N],
-    data_pointer: usize,
-    inst_pointer: usize,
-    op_list: Vec<Op>,
-    jump_positions: Vec<JumpPosition>, 
-}
-
-impl<const N: usize> Interpreter<N> {
-    fn new() -> Self {
-        Interpreter {
-            data: [0; N],
-            data_pointer: 0,
-            inst_pointer: 0,
-            op_list: Vec::<Op>::new(),
-            jump_positions: Vec::<JumpPosition>::new(),
-        }
-    }
-
-    /// Compile and run brainfuck code.
-    fn compile(&mut self, code: String, verbose: bool, display_chars: bool) {
-        let start = std::time::Instant::now();
-
-        // Clearing the Op list is only necessary in the REPL,
-        // so that the same Interpreter instance can be reused
-        self.op_list.clear();
-
-        let chars = code.as_bytes();
-        for c in chars {
-            match *c as char {
-                INCREMENT_DP =>         self.op_list.push(Op::IncrementDp),
-                DECREMENT_DP =>         self.op_list.push(Op::DecrementDp),
-                INCREMENT_DP_VALUE =>   self.op_list.push(Op::IncrementDpValue),
-                DECREMENT_DP_VALUE =>   self.op_list.push(Op::DecrementDpValue),
-                OUTPUT_DP =>            self.op_list.push(Op::OutputDp),
-                INPUT_DP =>             self.op_list.push(Op::InputDp),
-                JUMP_FORWARD =>         self.op_list.push(Op::JumpForward),
-                JUMP_BACK =>            self.op_list.push(Op::JumpBackward),
-                // any other character is ignored, so that brainfuck programs can contain whitespace and comments.                
-                _ => (),
+impl History {
+    /// Load the history saved by earlier sessions, keeping only the last `HISTORY_SIZE` lines.
+    /// Without a home directory the history only lasts for this session.
+    fn load() -> Self {
+        Self::open(env::var_os("HOME").map(|home| PathBuf::from(home).join(".brainfuck_history")))
+    }
+
+    /// Load the history saved at `path`, or start an empty one that isn't saved if there's no
+    /// path.
+    fn open(path: Option<PathBuf>) -> Self {
+        let mut lines: Vec<String> = path.as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(String::from).collect())
+            .unwrap_or_default();
+
+        // lines are only ever added to the file, so it is trimmed here
+        let excess = lines.len().saturating_sub(HISTORY_SIZE);
+        if excess > 0 {
+            lines.drain(..excess);
+            if let Some(path) = &path {
+                let _ = std::fs::write(path, lines.join("\n") + "\n");
             }
         }
+        History { lines, path }
+    }
 
-        if !self.validate_jumps() {
-            eprintln!("Execution stopped due to mismatched jump instructions.");
-        } else {
-            if verbose {
-                println!("Compilation succeeded in {:?}", start.elapsed());
-            }
-            let res = self.run(display_chars);
-            if !res {
-                eprintln!("Error occured during execution.");
-            }
-        }
-    }
-
-    /// Validates jumps (`[` and `]`) by ensuring each jump forward instruction has exactly one
-    /// corresponding jump backward instruction, and vice versa.
-    fn validate_jumps(&mut self) -> bool {
-        // In REPL mode, this needs to be cleared since the same Interpreter instance is reused.
-        self.jump_positions.clear();
-
-        // Use a Vec like a stack to validate the jumps    
-        let mut stack = Vec::<(Op, usize)>::new();
-        
-        for (index, op) in self.op_list.iter().enumerate() {
-            match *op {
-                // Push a jump forward instruction and its index in the Op list to the top of the
-                // stack                
-                Op::JumpForward => stack.push((Op::JumpForward, index)),
-                // When we come across a jump back instruction, there must be its corresponding
-                // jump forward instruction at the top of the stack.  
-                Op::JumpBackward => {
-                    // checking if the stack is empty first means the calls to `unwrap()` are safe
-                    if stack.is_empty() || stack.last().unwrap().0 != Op::JumpForward {
-                        eprintln!("Found mismatched jump instruction at Op {index}.");
-                        return false;
-                    }
+    /// Add a line to the history, saving it straight away so that it is kept even if the REPL
+    /// is exited with Ctrl-C. Empty lines and repeats of the line before aren't added.
+    fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.lines.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.lines.push(line.to_string());
+
+        // the REPL still works without saving its history, so any error is ignored
+        if let Some(path) = &self.path {
+            let _ = File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{line}"));
+        }
+    }
+}
+
+/// Read a line typed at the terminal after showing `prompt`, where the up and down arrows move
+/// back and forth through the lines in `history`. Returns `None` if Ctrl-D is pressed on an empty
+/// line.
+fn read_line(term: &Term, prompt: &str, history: &History) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    // the index in the history of the line being shown, which is one past the end for the line
+    // being typed, kept in `typed` while going through the history
+    let mut index = history.lines.len();
+    let mut typed = String::new();
 
-                    // now we know where the jump starts and ends
-                    let start = stack.pop().unwrap();
-                    self.jump_positions.push(JumpPosition {
-                        start: start.1,
-                        end: index,
-                    });
+    print!("{prompt}");
+    stdout().flush()?;
+    loop {
+        match term.read_key()? {
+            Key::Enter => {
+                println!();
+                return Ok(Some(line));
+            }
+            Key::Char('\u{4}') if line.is_empty() => {
+                println!();
+                return Ok(None);
+            }
+            Key::Backspace => {
+                line.pop();
+            }
+            Key::ArrowUp if index > 0 => {
+                if index == history.lines.len() {
+                    typed = mem::take(&mut line);
                 }
-                _ => (),
+                index -= 1;
+                line = history.lines[index].clone();
+            }
+            Key::ArrowDown if index < history.lines.len() => {
+                index += 1;
+                line = history.lines.get(index).cloned().unwrap_or_else(|| mem::take(&mut typed));
             }
-        } 
+            Key::Char(c) if !c.is_control() => line.push(c),
+            _ => continue,
+        }
 
-        stack.is_empty()
+        term.clear_line()?;
+        print!("{prompt}{line}");
+        stdout().flush()?;
     }
+}
 
-    /// Reset the instruction pointer to 0 and run the compiled list of instructions.
-    fn run(&mut self, display_chars: bool) -> bool {
-        self.inst_pointer = 0;
+/// Read the `.bf` file at `path`, then compile and run it with the REPL's Interpreter, so that it
+/// runs on the memory cells as they are and leaves them for the lines that follow.
+fn load<T: Cell>(interpreter: &mut Interpreter<T>, path: &str, options: &Options) -> Result<(), Box<dyn Error>> {
+    if !path.ends_with(".bf") {
+        return Err(format!("Error: file {path} was not a `.bf` file.").into());
+    }
+    let contents = std::fs::read(path).map_err(|e| format!("Error reading file: {e}"))?;
+    let contents = expand_includes(&contents, Path::new(path), &mut Vec::new(), options)?;
+    let (code, input) = split_input(&contents, options);
+    let program = compile(code, options)?;
+    execute(interpreter, &program, input, options)?;
+    Ok(())
+}
 
-        // Jump instructions will move the instruction pointer around the program
-        // and any other operation will increment it by 1.
-        // So just run until the list of operations in exhausted.
-        while self.inst_pointer < self.op_list.len() {
-            match self.op_list[self.inst_pointer] {
-                Op::IncrementDp => {
-                    self.increment_dp();
-                    self.inst_pointer += 1;
-                }
-                Op::DecrementDp => {
-                    self.decrement_dp();
-                    self.inst_pointer += 1;
-                }
-                Op::IncrementDpValue => {
-                    self.increment_dp_value();
-                    self.inst_pointer += 1;
-                }
-                Op::DecrementDpValue => {
-                    self.decrement_dp_value();
-                    self.inst_pointer += 1;
-                }
-                Op::OutputDp => {
-                    self.output_dp(display_chars);
-                    self.inst_pointer += 1;
-                }
-                Op::InputDp => {
-                    self.input_dp();
-                    self.inst_pointer += 1;
+/// Set by Ctrl-C to interrupt the program the REPL is running.
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Whether the REPL is running a program, in which case Ctrl-C interrupts the program rather
+/// than exiting.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Handle Ctrl-C by interrupting the program the REPL is running, or if it is waiting at the
+/// prompt, by exiting as usual. Returns the flag to interrupt the Interpreter with.
+#[cfg(unix)]
+fn handle_interrupts() -> Arc<AtomicBool> {
+    extern "C" fn handler(_: libc::c_int) {
+        match INTERRUPT.get() {
+            Some(interrupt) if RUNNING.load(Ordering::Relaxed) => interrupt.store(true, Ordering::Relaxed),
+            // only async-signal-safe functions can be called here, so exit straight away
+            _ => unsafe { libc::_exit(130) },
+        }
+    }
+
+    let interrupt = INTERRUPT.get_or_init(Default::default).clone();
+    unsafe {
+        libc::signal(libc::SIGINT, handler as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+    interrupt
+}
+
+/// Ctrl-C can only be handled on Unix, elsewhere it exits the REPL as usual.
+#[cfg(not(unix))]
+fn handle_interrupts() -> Arc<AtomicBool> {
+    INTERRUPT.get_or_init(Default::default).clone()
+}
+
+/// The number of `[` in the code that haven't been closed yet.
+/// As soon as there is a `]` without a `[` to close this is negative, so that the error can be
+/// reported straight away. The brackets are those of `commands`.
+fn open_jumps(code: &str, commands: Commands) -> isize {
+    let mut depth = 0;
+    for byte in code.bytes() {
+        match commands.command(byte) {
+            Some('[') => depth += 1,
+            Some(']') => depth -= 1,
+            _ => (),
+        }
+        if depth < 0 {
+            break;
+        }
+    }
+    depth
+}
+
+/// Write the region of memory holding non-zero cells, extended to include the data pointer, to
+/// stderr. Each cell is shown with its index, value and the character of that value, with the data
+/// pointer marked.
+fn dump_memory<T: Cell>(interpreter: &Interpreter<T>) {
+    let data = interpreter.data();
+    let data_pointer = interpreter.data_pointer();
+
+    let first = data.iter().position(|value| !value.is_zero()).unwrap_or(data_pointer);
+    let last = data.iter().rposition(|value| !value.is_zero()).unwrap_or(data_pointer);
+    let (start, end) = (first.min(data_pointer), last.max(data_pointer));
+
+    for (index, &value) in data.iter().enumerate().take(end + 1).skip(start) {
+        let c = char::from_u32(value.into())
+            .filter(|c| c.is_ascii_graphic() || *c == ' ')
+            .unwrap_or('.');
+        let marker = if index == data_pointer { " <-" } else { "" };
+        eprintln!("{index:>8}: {value:>3} {c}{marker}");
+    }
+}
+
+/// Write the memory cells from 0 up to the highest cell the data pointer reached to stderr,
+/// along with the final position of the data pointer. If a `window` of the first cell and the
+/// number of cells is given, only the cells within it are written instead. With `ascii_art`, the
+/// cells are drawn as a table like `hexdump -C` rather than one to a line.
+fn dump_tape<T: Cell>(interpreter: &Interpreter<T>, window: Option<(usize, usize)>, ascii_art: bool) {
+    let data = interpreter.data();
+    let (start, len) = window.unwrap_or((0, interpreter.high_water_mark() + 1));
+    eprintln!();
+    eprintln!("Data pointer: {}", interpreter.data_pointer());
+    if ascii_art {
+        let end = (start + len).min(data.len());
+        eprint!("{}", hex_table(&data[start.min(end)..end], start));
+        return;
+    }
+    for (index, value) in data.iter().enumerate().skip(start).take(len) {
+        eprintln!("{index:>8}: {value:>3}");
+    }
+}
+
+/// The number of cells in each row of the table drawn by `hex_table`.
+const HEX_TABLE_WIDTH: usize = 16;
+
+/// Draw `cells`, the first of which is cell `first`, as a boxed table like `hexdump -C`: each row
+/// holds the cells whose indices only differ in their last hexadecimal digit, starting with the
+/// index of its first cell, then each value in hexadecimal under a header of the index mod 16, and
+/// then each value as a character, with `.` for those that aren't printable. Cells outside of
+/// `cells` are left blank in the first and last rows.
+fn hex_table<T: Cell>(cells: &[T], first: usize) -> String {
+    // every value of a cell needs the same number of digits to keep the columns lined up
+    let digits = mem::size_of::<T>() * 2;
+    let hex_width = HEX_TABLE_WIDTH * (digits + 1) + 1;
+    let border = format!("+----------+{}+{}+\n", "-".repeat(hex_width), "-".repeat(HEX_TABLE_WIDTH + 2));
+
+    let mut table = border.clone();
+    table.push_str("|          |");
+    for column in 0..HEX_TABLE_WIDTH {
+        table.push_str(&format!(" {column:0digits$x}"));
+    }
+    table.push_str(" | ");
+    for column in 0..HEX_TABLE_WIDTH {
+        table.push_str(&format!("{column:x}"));
+    }
+    table.push_str(" |\n");
+    table.push_str(&border);
+
+    let end = first + cells.len();
+    let mut row = first - first % HEX_TABLE_WIDTH;
+    while row < end {
+        let mut hex = String::new();
+        let mut text = String::new();
+        for index in row..row + HEX_TABLE_WIDTH {
+            match index.checked_sub(first).and_then(|offset| cells.get(offset)) {
+                Some(&value) => {
+                    let value: u32 = value.into();
+                    hex.push_str(&format!(" {value:0digits$x}"));
+                    text.push(char::from_u32(value).filter(|c| c.is_ascii_graphic() || *c == ' ').unwrap_or('.'));
                 }
-                Op::JumpForward => {
-                    if !self.jump_forward() {
-                        return false;
-                    }
-                } 
-                Op::JumpBackward => {
-                    if !self.jump_backward() {
-                        return false;
-                    }
+                None => {
+                    hex.push_str(&" ".repeat(digits + 1));
+                    text.push(' ');
                 }
             }
         }
+        table.push_str(&format!("| {row:08x} |{hex} | {text} |\n"));
+        row += HEX_TABLE_WIDTH;
+    }
+    table.push_str(&border);
+    table
+}
 
-        true
+/// Write to stderr how many of the memory cells from 0 up to the highest cell the data pointer
+/// reached hold each value, for the values that any of them hold.
+fn print_histogram<T: Cell>(interpreter: &Interpreter<T>) {
+    let mut counts = BTreeMap::<u32, usize>::new();
+    for &value in &interpreter.data()[..=interpreter.high_water_mark()] {
+        *counts.entry(value.into()).or_default() += 1;
     }
-    
 
-    #[inline]
-    fn increment_dp(&mut self) {
-        if self.data_pointer == DATA_SIZE - 1 {
-            panic!("Cannot increment data pointer above data size {DATA_SIZE}.");
+    eprintln!();
+    eprintln!("Cell values:");
+    for (value, count) in counts {
+        eprintln!("{value:>8}: {count}");
+    }
+}
+
+/// Write out a summary of the structure of the program without running it: how many of each
+/// command it does the work of, how many Ops it compiled to, and how many loops it has and how
+/// deeply they are nested. The commands are counted in the decompiled program, so loops that were
+/// optimized into a single Op still count, but commands that cancel out like `+-` don't.
+fn print_stats(program: &Program) {
+    let code = brainfuck::decompile(program.ops());
+    let mut depth = 0;
+    let mut max_depth = 0;
+    for c in code.chars() {
+        match c {
+            '[' => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            ']' => depth -= 1,
+            _ => (),
         }
-        self.data_pointer += 1;
     }
 
-    #[inline]
-    fn decrement_dp(&mut self) {
-        if self.data_pointer == 0 {
-            panic!("Cannot decrement data pointer below 0.");
+    for command in ['>', '<', '+', '-', '.', ',', '[', ']', '#', '?', '='] {
+        let count = code.chars().filter(|&c| c == command).count();
+        // breakpoints and assertions are only counted when they are compiled
+        if !matches!(command, '#' | '?' | '=') || count > 0 {
+            println!("{command:<12}{count}");
         }
-        self.data_pointer -= 1;
     }
+    println!("{:<12}{}", "Ops", program.ops().len());
+    println!("{:<12}{}", "Loops", code.matches('[').count());
+    println!("{:<12}{max_depth}", "Max depth");
+}
 
-    #[inline]
-    fn increment_dp_value(&mut self) {
-        self.data[self.data_pointer] = match self.data[self.data_pointer] {
-            i8::MAX => i8::MIN,
-            _ => self.data[self.data_pointer] + 1,  
-        };
+/// Write the final state to stdout as a single line of JSON, for other tools to read: the position
+/// of the data pointer, the number of instructions executed, and the memory cells from 0 up to the
+/// highest cell the data pointer reached.
+fn dump_json<T: Cell>(interpreter: &Interpreter<T>) {
+    let tape = interpreter.data()[..=interpreter.high_water_mark()]
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!(
+        "{{ \"data_pointer\": {}, \"instruction_count\": {}, \"tape\": [{tape}] }}",
+        interpreter.data_pointer(),
+        interpreter.instructions_executed()
+    );
+}
+
+/// Read the given file, create and instance of the Interpreter struct and run the file.
+/// Path given to this function has already been checked to be a `.bf` file of code or a `.bfc`
+/// file of bytecode, and any errors encountered while reading the file are reported.
+fn run_file<T: Cell>(file_path: &String, options: &Options) -> Result<(), Box<dyn Error>> {
+    // the file is read as bytes, so that comments don't need to be valid UTF-8
+    let contents = std::fs::read(file_path)?;
+    if file_path.ends_with(".bfc") {
+        if options.rewrite.is_some() {
+            eprintln!("Error: only code can be rewritten, not bytecode.");
+            return Ok(());
+        }
+        // bytecode has already been compiled, so it can be run straight away
+        match Program::from_bytecode(&contents) {
+            Ok(program) => run_program::<T>(&program, None, options),
+            Err(e) => {
+                eprintln!("{e}");
+                check_failed(options);
+            }
+        }
+    } else if options.from_ook {
+        run_code::<T>(&contents, options);
+    } else {
+        match expand_includes(&contents, Path::new(file_path), &mut Vec::new(), options) {
+            Ok(code) => run_code::<T>(&code, options),
+            Err(e) => {
+                eprintln!("{e}");
+                check_failed(options);
+            }
+        }
     }
+    Ok(())
+}
+
+/// The start of a line that splices another file into the code, such as `;include "print.bf"`.
+const INCLUDE: &[u8] = b";include ";
 
-    #[inline]
-    fn decrement_dp_value(&mut self) {
-        self.data[self.data_pointer] = match self.data[self.data_pointer] {
-            i8::MIN => i8::MAX,
-            _ => self.data[self.data_pointer] - 1,  
+/// Replace each line of the code read from `path` that is an include directive, such as
+/// `;include "print.bf"`, with the code of the file it names, relative to the directory of `path`.
+/// Included files can include others in turn, and only their code before any `!` is spliced in.
+/// `including` holds the files that the code is being included into, so that a file including
+/// itself, even through other files, is an error rather than never ending.
+fn expand_includes(code: &[u8], path: &Path, including: &mut Vec<PathBuf>, options: &Options) -> Result<Vec<u8>, String> {
+    let canonical = path.canonicalize().map_err(|e| format!("Error reading file {}: {e}", path.display()))?;
+    if including.contains(&canonical) {
+        return Err(format!("Error: {} is included from within itself.", path.display()));
+    }
+    including.push(canonical);
+
+    let mut expanded = Vec::with_capacity(code.len());
+    let mut lines = code.split_inclusive(|&byte| byte == b'\n');
+    for line in lines.by_ref() {
+        let Some(name) = line.trim_ascii().strip_prefix(INCLUDE) else {
+            expanded.extend_from_slice(line);
+            // anything after a `!` is the program's input, not code
+            if !options.no_bang && line.contains(&b'!') {
+                break;
+            }
+            continue;
         };
+
+        // the line is removed even though it is a comment, since the `.` of most names is a command
+        let name = name.trim_ascii()
+            .strip_prefix(b"\"")
+            .and_then(|name| name.strip_suffix(b"\""))
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .ok_or_else(|| format!("Error: invalid include in {}, the file name should be in quotes.", path.display()))?;
+        let included_path = path.parent().unwrap_or(Path::new("")).join(name);
+        let contents = std::fs::read(&included_path)
+            .map_err(|e| format!("Error reading file {}: {e}", included_path.display()))?;
+        let (included, _) = split_input(&contents, options);
+        expanded.extend(expand_includes(included, &included_path, including, options)?);
+        if line.ends_with(b"\n") && !expanded.ends_with(b"\n") {
+            expanded.push(b'\n');
+        }
+    }
+    expanded.extend(lines.flatten());
+
+    including.pop();
+    Ok(expanded)
+}
+
+/// Split the code at the first `!`, into the code before it and the input for the program after
+/// it, unless `--no-bang` was given.
+fn split_input<'a>(code: &'a [u8], options: &Options) -> (&'a [u8], Option<&'a [u8]>) {
+    match code.iter().position(|&byte| byte == b'!') {
+        Some(bang) if !options.no_bang => (&code[..bang], Some(&code[bang + 1..])),
+        _ => (code, None),
+    }
+}
+
+/// Compile and run the code, reporting any errors.
+/// Unless `--no-bang` was given, anything after the first `!` is the input for the program rather
+/// than code.
+fn run_code<T: Cell>(code: &[u8], options: &Options) {
+    // Ook! is translated first, since its words end with `!`
+    let translated;
+    let code = if options.from_ook {
+        match brainfuck::ook_to_brainfuck(code) {
+            Ok(brainfuck) => translated = brainfuck,
+            Err(e) => {
+                eprintln!("{e}");
+                return;
+            }
+        }
+        translated.as_bytes()
+    } else {
+        code
+    };
+    let (code, input) = split_input(code, options);
+
+    if let Some(rewrite) = options.rewrite {
+        if let Err(e) = rewrite_code(code, input, rewrite, options) {
+            eprintln!("{e}");
+        }
+        return;
+    }
+
+    match compile(code, options) {
+        Ok(program) => {
+            warn_endless(&program, options);
+            run_program::<T>(&program, input, options)
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            check_failed(options);
+        }
+    }
+}
+
+/// With `--check`, exit with an error status once an error has been reported, so that scripts can
+/// tell that the code didn't compile.
+fn check_failed(options: &Options) {
+    if options.check {
+        process::exit(1);
+    }
+}
+
+/// Run the program, or if a language to emit was chosen, write out the program translated into
+/// that language instead, or if disassembling, decompiling, drawing the control flow graph,
+/// summarizing the program or saving bytecode, write that out. When only checking that the
+/// program compiles, nothing is done. Any errors are reported. `,` reads from the `--input` file if
+/// one was given, or otherwise from `input` if it is given, rather than stdin.
+/// When benchmarking, the program is run repeatedly and timed instead, and when diffing, it is
+/// compared with another program.
+fn run_program<T: Cell>(program: &Program, input: Option<&[u8]>, options: &Options) {
+    let input = options.input.as_deref().or(input);
+    let result: Result<(), Box<dyn Error>> = if let Some(language) = options.emit {
+        emit(program, language, options)
+    } else if options.disasm {
+        disassemble(program);
+        Ok(())
+    } else if options.decompile {
+        println!("{}", brainfuck::decompile(program.ops()));
+        Ok(())
+    } else if options.cfg_dot {
+        print!("{}", brainfuck::control_flow_dot(program));
+        Ok(())
+    } else if options.stats {
+        print_stats(program);
+        Ok(())
+    } else if options.check {
+        // the program compiled, so there is nothing else to check
+        Ok(())
+    } else if let Some(ref path) = options.emit_bytecode {
+        std::fs::write(path, program.to_bytecode()).map_err(|e| e.into())
+    } else if let Some(runs) = options.bench {
+        bench::<T>(program, input, runs, options).map_err(|e| e.into())
+    } else if let Some(ref other) = options.diff {
+        diff::<T>(program, other, input, options)
+    } else {
+        let mut interpreter = interpreter::<T>(options);
+        let result = execute(&mut interpreter, program, input, options);
+        if options.dump {
+            dump_tape(&interpreter, options.dump_window, options.ascii_art);
+        }
+        if options.dump_json {
+            dump_json(&interpreter);
+        }
+        if options.histogram {
+            print_histogram(&interpreter);
+        }
+        result.map_err(|e| e.into())
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+    }
+}
+
+/// Run the program and the program in the file at `other` on new Interpreters, each with their
+/// output discarded, then write out each memory cell whose final value differs between them. Both
+/// programs read the same input, either `input` if it is given or no input at all.
+fn diff<T: Cell>(program: &Program, other: &str, input: Option<&[u8]>, options: &Options) -> Result<(), Box<dyn Error>> {
+    let contents = std::fs::read(other).map_err(|e| format!("Error reading file {other}: {e}"))?;
+    let other_program = if other.ends_with(".bfc") {
+        Program::from_bytecode(&contents)?
+    } else {
+        let contents = expand_includes(&contents, Path::new(other), &mut Vec::new(), options)?;
+        // the input after the other program's `!` is ignored, so that both read the same input
+        let (code, _) = split_input(&contents, options);
+        compile(code, options)?
+    };
+
+    let mut first = interpreter::<T>(options);
+    first.run(program, &mut input.unwrap_or_default(), &mut Sink::new())?;
+    let mut second = interpreter::<T>(options);
+    second.run(&other_program, &mut input.unwrap_or_default(), &mut Sink::new())?;
+
+    // the arrays can be different lengths if they grew, in which case the missing cells are 0
+    let (first, second) = (first.data(), second.data());
+    let differences: Vec<(usize, T, T)> = (0..first.len().max(second.len()))
+        .map(|index| (index, first.get(index).copied().unwrap_or_default(), second.get(index).copied().unwrap_or_default()))
+        .filter(|(_, a, b)| a != b)
+        .collect();
+
+    if differences.is_empty() {
+        println!("The memory cells are the same.");
+    }
+    for (index, a, b) in differences {
+        println!("{index:>8}: {a:>3} {b:>3}");
+    }
+    Ok(())
+}
+
+/// Run the program `runs` times, each time with a new Interpreter and with its output discarded,
+/// then write out the fastest, median, mean and slowest time taken by a run.
+/// Every run reads the same input, either `input` if it is given or no input at all, so that the
+/// runs can be compared.
+fn bench<T: Cell>(program: &Program, input: Option<&[u8]>, runs: usize, options: &Options) -> Result<(), BfError> {
+    let mut times = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let mut interpreter = interpreter::<T>(options);
+        let mut input = input.unwrap_or_default();
+        let start = Instant::now();
+        interpreter.run(program, &mut input, &mut Sink::new())?;
+        times.push(start.elapsed());
+    }
+
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / runs as u32;
+    println!("{:<8}{runs}", "Runs");
+    println!("{:<8}{:?}", "Min", times[0]);
+    println!("{:<8}{:?}", "Median", times[runs / 2]);
+    println!("{:<8}{mean:?}", "Mean");
+    println!("{:<8}{:?}", "Max", times[runs - 1]);
+    Ok(())
+}
+
+/// Write out the program translated into `language`, either to stdout or to the output file if
+/// one was given.
+fn emit(program: &Program, language: Language, options: &Options) -> Result<(), Box<dyn Error>> {
+    let source = match language {
+        Language::C => brainfuck::transpile_c(program, options.cells),
+        Language::Rust => brainfuck::transpile_rust(program, options.cells),
+    };
+
+    match options.output {
+        Some(ref path) => std::fs::write(path, source)?,
+        None => print!("{source}"),
+    }
+    Ok(())
+}
+
+/// Write out the code pretty printed or minified, followed by the input after the `!` if there
+/// was one, so that the rewritten program still runs the same way.
+fn rewrite_code(code: &[u8], input: Option<&[u8]>, rewrite: Rewrite, options: &Options) -> Result<(), Box<dyn Error>> {
+    warn_unmatched(code, options);
+    let compile_options = compile_options(options);
+    let rewritten = match rewrite {
+        Rewrite::Format => brainfuck::format_code(code, &compile_options)?,
+        Rewrite::Minify => brainfuck::minify_code(code, &compile_options, false)?,
+        Rewrite::MinifyAggressive => brainfuck::minify_code(code, &compile_options, true)?,
+    };
+    let mut out = stdout().lock();
+    out.write_all(rewritten.as_bytes())?;
+    if let Some(input) = input {
+        out.write_all(b"!")?;
+        out.write_all(input)?;
+    }
+    Ok(())
+}
+
+/// Write out the list of Ops of the program, numbered by their index, along with where in the
+/// code each one came from.
+fn disassemble(program: &Program) {
+    for (index, (op, position)) in program.ops().iter().zip(program.positions()).enumerate() {
+        println!("{index:04} {:<32} {position}", op.to_string());
     }
+}
+
+/// Create an Interpreter configured by the command line options.
+fn interpreter<T: Cell>(options: &Options) -> Interpreter<T> {
+    let mut interpreter = Interpreter::with_size(options.cells);
+    interpreter.load(&options.init);
+    interpreter.set_eof_mode(options.eof_mode);
+    interpreter.set_output_mode(options.output_mode);
+    interpreter.set_growable(options.grow);
+    interpreter.set_wrapping(options.wrap_pointer);
+    interpreter.set_unchecked(options.unsafe_fast);
+    interpreter.set_trace(options.trace);
+    interpreter.set_watches(options.watches.clone());
+    interpreter.set_guard(options.guard);
+    interpreter.set_max_steps(options.max_steps);
+    interpreter.set_input_limit(options.input_limit);
+    interpreter.set_timeout(options.timeout);
+    interpreter.set_profiling(options.profile);
+    interpreter
+}
 
-    fn input_dp(&mut self) {
-        let term = Term::stdout();
-        match term.read_char() {
-            Ok(c) => self.data[self.data_pointer] = c as i8,
-            Err(e) => panic!("Invalid character input: {e}"),
-        } 
+/// The options to compile code with: `#` is a breakpoint in debug mode, comments are rejected in
+/// strict mode, and the program is optimized at the chosen level.
+fn compile_options(options: &Options) -> CompileOptions {
+    CompileOptions {
+        breakpoints: options.debug,
+        test_ops: options.test_ops,
+        strict: options.strict,
+        opt_level: options.opt_level,
+        commands: options.commands,
+        max_depth: Some(options.max_depth),
+        auto_close: options.auto_close,
     }
+}
 
-    #[inline]
-    fn output_dp(&self, display_chars: bool) {
-        if display_chars {
-            print!("{}", self.data[self.data_pointer] as u8 as char);
+/// With `--auto-close`, warn about each jump that will be closed or ignored when the code is
+/// compiled.
+fn warn_unmatched(code: &[u8], options: &Options) {
+    if !options.auto_close {
+        return;
+    }
+    for (jump, position) in brainfuck::unmatched_jumps(code, options.commands) {
+        if jump == '[' {
+            eprintln!("Warning: the '[' at {position} is unmatched, so a ']' is added at the end of the code.");
         } else {
-            print!("{}", self.data[self.data_pointer]);
+            eprintln!("Warning: the ']' at {position} is unmatched, so it is removed.");
+        }
+    }
+}
+
+/// Compile the code, with `#` as a breakpoint in debug mode, and rejecting comments in strict
+/// mode. In verbose mode, the time taken to compile is also written out. With `--opt-report`, the
+/// optimizations applied are written to stderr.
+fn compile(code: &[u8], options: &Options) -> Result<Program, BfError> {
+    warn_unmatched(code, options);
+    let start = Instant::now();
+    let compile_options = compile_options(options);
+    let (program, report) = brainfuck::compile_with_report(code, &compile_options)?;
+    if options.verbose {
+        println!("Compilation succeeded in {:?}", start.elapsed());
+    }
+    if options.opt_report {
+        eprintln!("Optimizations: {report}");
+    }
+    Ok(program)
+}
+
+/// Warn about each loop that can never end once it is entered. This is only known when the
+/// program starts with every cell at 0 and the data pointer can't wrap around, so nothing is
+/// warned about with `--init`, `--init-file`, `--fill` or `--wrap-pointer`.
+fn warn_endless(program: &Program, options: &Options) {
+    if options.wrap_pointer || options.init.iter().any(|&byte| byte != 0) {
+        return;
+    }
+    for position in program.endless_loops() {
+        eprintln!("Warning: the loop at {position} never ends once it is entered.");
+    }
+}
+
+/// Compile the code and run it with the given Interpreter.
+fn compile_and_run<T: Cell>(interpreter: &mut Interpreter<T>, code: &[u8], options: &Options) -> Result<(), BfError> {
+    let program = compile(code, options)?;
+    execute(interpreter, &program, None, options)
+}
+
+/// Run the program with the given Interpreter, using stdout for output, and `input` for input if
+/// it is given. In quiet mode, the output is only counted rather than written out, and with
+/// `--out` it is also written to a file.
+fn execute<T: Cell>(
+    interpreter: &mut Interpreter<T>,
+    program: &Program,
+    input: Option<&[u8]>,
+    options: &Options,
+) -> Result<(), BfError> {
+    let (mut sink, mut stdout) = (Sink::new(), BufWriter::new(stdout()));
+    let output: &mut dyn Write = if options.quiet { &mut sink } else { &mut stdout };
+    let mut tee;
+    let mut output = match options.out {
+        Some(ref file) => {
+            tee = Tee::new(output, BufWriter::new(file));
+            &mut tee as &mut dyn Write
         }
+        None => output,
+    };
+    let mut input = source(input, options.echo);
+    if options.normalize_newlines {
+        input = Box::new(NormalizeNewlines::new(input));
+    }
+    let start = Instant::now();
+    let result = if options.visual {
+        visual(interpreter, program, &mut input, &mut output)
+    } else if options.debug {
+        debug(interpreter, program, &mut input, &mut output, &options.breaks)
+    } else {
+        interpreter.run(program, &mut input, &mut output).map(|_| ())
+    };
+    let elapsed = start.elapsed();
+
+    if options.quiet {
+        eprintln!("Output bytes: {}", sink.written());
+    }
+
+    if options.verbose {
+        println!();
+        println!("Execution finished in {elapsed:?}");
+        println!("Max cell reached: {}", interpreter.high_water_mark());
+        println!("Instructions executed: {}", interpreter.instructions_executed());
+        println!("Output bytes: {}", interpreter.bytes_written());
+    }
+
+    // the profile is still useful when the program was stopped part way through
+    if options.profile {
+        print_profile(interpreter, program);
     }
+    result
+}
 
-    fn jump_forward(&mut self) -> bool {
-        // Called when we encounter a jump forward instruction.
-        // If the byte at the data pointer is 0, we need to jump just beyond the corresponding jump
-        // back instruction. We can find the JumpInstruction struct from the Vec that has the same
-        // `start` value as the current instruction pointer, so we move 1 beyond its `end`.
-        if self.data[self.data_pointer] == 0 {
-            let jp = self.jump_positions.iter().find(|&j| j.start == self.inst_pointer);
-            if let Some(jump) = jp {
-                self.inst_pointer = jump.end + 1;
-            } else {
-                eprintln!("No jump position found for jump at Op index {}, there was a problem during compilation.", self.inst_pointer);
-                return false;
+/// Step through the program, stopping at each breakpoint. If there are any conditions, breakpoints
+/// are passed over unless one of the conditions holds.
+fn debug<T: Cell>(
+    interpreter: &mut Interpreter<T>,
+    program: &Program,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    conditions: &[Condition],
+) -> Result<(), BfError> {
+    interpreter.rewind();
+    loop {
+        match interpreter.step(program, input, output) {
+            StepResult::Continue => (),
+            StepResult::Breakpoint if conditions.is_empty() => breakpoint(interpreter, program),
+            StepResult::Breakpoint => {
+                if let Some(condition) = conditions.iter().find(|condition| condition.holds(interpreter.data())) {
+                    conditional_breakpoint(interpreter, program, condition);
+                }
             }
-        } else {
-            // or else just increment by 1.
-            self.inst_pointer += 1;
+            StepResult::Halted => return Ok(()),
+            StepResult::Error(e) => return Err(e),
         }
+    }
+}
+
+/// The number of loops shown in the profile.
+const PROFILE_LOOPS: usize = 10;
+
+/// Write a summary of the profile of the last run to stderr: the total number of instructions
+/// executed, the number executed of each kind of Op, and the loops that iterated the most.
+fn print_profile<T: Cell>(interpreter: &Interpreter<T>, program: &Program) {
+    let Some(profile) = interpreter.profile() else {
+        return;
+    };
+    let ops = program.ops();
 
-        true
+    eprintln!();
+    eprintln!("Profile");
+    eprintln!("Total instructions executed: {}", profile.iter().sum::<u64>());
+
+    // kinds of Op in order of first appearance, with their counts
+    let mut kinds = Vec::<(&str, u64)>::new();
+    for (op, &count) in ops.iter().zip(profile) {
+        match kinds.iter_mut().find(|(name, _)| *name == op.name()) {
+            Some((_, total)) => *total += count,
+            None => kinds.push((op.name(), count)),
+        }
+    }
+    kinds.sort_by_key(|&(_, count)| Reverse(count));
+    eprintln!();
+    for (name, count) in kinds {
+        eprintln!("{name:>16}: {count}");
     }
 
-    fn jump_backward(&mut self) -> bool {
-        // Called when we encounter a jump backward instruction.
-        // If the byte at the data pointer is non 0, we go back just beyond the corresponding jump
-        // forward instruction.
-        if self.data[self.data_pointer] != 0 {
-            let jp = self.jump_positions.iter().find(|&j| j.end == self.inst_pointer);
-            if let Some(jump) = jp {
-                self.inst_pointer = jump.start + 1;
-            } else {
-                eprintln!("No jump position found for jump at Op index {}, there was a problem during compilation.", self.inst_pointer);
-                return false;
+    // a loop iterates each time its `]` jumps back
+    let mut loops = Vec::<(usize, u64)>::new();
+    for (op, &count) in ops.iter().zip(profile) {
+        if let Op::JumpBackward(start) = op {
+            if count > 0 {
+                loops.push((*start, count));
             }
+        }
+    }
+    loops.sort_by_key(|&(_, count)| Reverse(count));
+    if !loops.is_empty() {
+        eprintln!();
+        eprintln!("Most executed loops:");
+        for (start, count) in loops.into_iter().take(PROFILE_LOOPS) {
+            eprintln!("  loop at {}: {count} iterations", program.positions()[start]);
+        }
+    }
+}
+
+/// The number of cells either side of the data pointer shown by `--visual`.
+const VISUAL_WINDOW: usize = 8;
+
+/// Step through the program one Op at a time, executing the next Op each time a key is pressed,
+/// and pressing `q` stops the program. Before each step the memory cells around the data pointer,
+/// the Op about to be executed and the output so far are drawn on the terminal, in place of the
+/// last drawing. The output is only written to `output` once the program has stopped, so that it
+/// doesn't get in the way of the drawing.
+fn visual<T: Cell>(
+    interpreter: &mut Interpreter<T>,
+    program: &Program,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<(), BfError> {
+    let term = Term::stderr();
+    let mut written = Vec::new();
+    // the number of lines of the last drawing, to be cleared before drawing again
+    let mut lines = 0;
+    interpreter.rewind();
+
+    let result = loop {
+        let view = visual_view(interpreter, program, &written);
+        let _ = term.clear_last_lines(lines);
+        let _ = term.write_str(&view);
+        lines = view.lines().count();
+
+        if interpreter.inst_pointer() >= program.ops().len() {
+            break Ok(());
+        }
+        if let Ok(Key::Char('q')) | Err(_) = term.read_key() {
+            break Ok(());
+        }
+        match interpreter.step(program, input, &mut written) {
+            StepResult::Continue | StepResult::Breakpoint => (),
+            StepResult::Halted => break Ok(()),
+            StepResult::Error(e) => break Err(e),
+        }
+    };
+
+    output.write_all(&written).map_err(BfError::OutputError)?;
+    result
+}
+
+/// Draw the Op at the instruction pointer and where it came from, a strip of the memory cells
+/// around the data pointer with their indices above them and the cell at the data pointer
+/// highlighted, and the output written so far with any control characters escaped.
+fn visual_view<T: Cell>(interpreter: &Interpreter<T>, program: &Program, written: &[u8]) -> String {
+    let inst_pointer = interpreter.inst_pointer();
+    let mut view = match program.ops().get(inst_pointer) {
+        Some(op) => format!("{inst_pointer:04} {op} at {}\n", program.positions()[inst_pointer]),
+        None => String::from("The program has finished.\n"),
+    };
+
+    let data = interpreter.data();
+    let data_pointer = interpreter.data_pointer();
+    let start = data_pointer.saturating_sub(VISUAL_WINDOW);
+    let end = (data_pointer + VISUAL_WINDOW).min(data.len() - 1);
+    let mut indices = String::new();
+    let mut cells = String::new();
+    for (index, value) in data.iter().enumerate().take(end + 1).skip(start) {
+        indices.push_str(&format!("{index:>6}"));
+        let cell = format!("{value:>6}");
+        if index == data_pointer {
+            cells.push_str(&console::style(cell).reverse().to_string());
         } else {
-            self.inst_pointer += 1;
+            cells.push_str(&cell);
+        }
+    }
+    view.push_str(&format!("{indices}\n{cells}\n"));
+
+    let text = String::from_utf8_lossy(written).escape_debug().to_string();
+    view.push_str(&format!("Output: {text}\n"));
+    if inst_pointer < program.ops().len() {
+        view.push_str("Press any key to step, or q to quit.\n");
+    }
+    view
+}
+
+/// The number of cells either side of the data pointer shown at a breakpoint.
+const BREAKPOINT_WINDOW: usize = 4;
+
+/// Called when a breakpoint has been hit in debug mode.
+/// Writes the data pointer and the cells around it to stderr, then if running interactively, waits
+/// for a key to be pressed before continuing.
+fn breakpoint<T: Cell>(interpreter: &Interpreter<T>, program: &Program) {
+    // the instruction pointer has already moved past the breakpoint
+    let position = program.positions()[interpreter.inst_pointer() - 1];
+    let data = interpreter.data();
+    let data_pointer = interpreter.data_pointer();
+
+    eprintln!();
+    eprintln!("Breakpoint at {position}, data pointer {data_pointer}");
+    let start = data_pointer.saturating_sub(BREAKPOINT_WINDOW);
+    let end = (data_pointer + BREAKPOINT_WINDOW).min(data.len() - 1);
+    for (index, value) in data.iter().enumerate().take(end + 1).skip(start) {
+        let marker = if index == data_pointer { " <-" } else { "" };
+        eprintln!("{index:>8}: {value:>3}{marker}");
+    }
+
+    wait_for_key();
+}
+
+/// Called when a breakpoint has been hit in debug mode and `condition` holds.
+/// Writes the condition and the memory cells like the `:mem` command of the REPL to stderr, then
+/// if running interactively, waits for a key to be pressed before continuing.
+fn conditional_breakpoint<T: Cell>(interpreter: &Interpreter<T>, program: &Program, condition: &Condition) {
+    let position = program.positions()[interpreter.inst_pointer() - 1];
+    eprintln!();
+    eprintln!("Breakpoint at {position}, {condition}");
+    dump_memory(interpreter);
+    wait_for_key();
+}
+
+/// If running interactively, wait for a key to be pressed.
+fn wait_for_key() {
+    if stdin().is_terminal() {
+        eprint!("Press any key to continue...");
+        // any error here just means we carry on without waiting
+        let _ = Term::stderr().read_key();
+        eprintln!();
+    }
+}
+
+/// A condition on the value of a memory cell, which a breakpoint can be given so that it only
+/// stops the program when the condition holds.
+struct Condition {
+    cell: usize,
+    comparison: Comparison,
+    value: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// The symbol of each comparison, with those of two characters first, so that `<=` isn't parsed
+/// as `<` followed by `=`.
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+impl Condition {
+    /// Parse a condition like `cell[3]==10`: a cell index in `cell[]`, one of the comparisons
+    /// `==`, `!=`, `<`, `<=`, `>` or `>=`, and a value. Whitespace is ignored.
+    fn parse(text: &str) -> Option<Condition> {
+        let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        let (cell, rest) = text.strip_prefix("cell[")?.split_once(']')?;
+        let (comparison, value) = COMPARISONS
+            .iter()
+            .find_map(|&(symbol, comparison)| Some((comparison, rest.strip_prefix(symbol)?)))?;
+
+        Some(Condition {
+            cell: cell.parse().ok()?,
+            comparison,
+            value: value.parse().ok()?,
+        })
+    }
+
+    /// Whether the condition holds for the memory cells. A cell past the end of `data` is treated
+    /// as 0, since it would be if the array grew to reach it.
+    fn holds<T: Cell>(&self, data: &[T]) -> bool {
+        let cell: u32 = data.get(self.cell).map_or(0, |&value| value.into());
+        match self.comparison {
+            Comparison::Equal => cell == self.value,
+            Comparison::NotEqual => cell != self.value,
+            Comparison::Less => cell < self.value,
+            Comparison::LessOrEqual => cell <= self.value,
+            Comparison::Greater => cell > self.value,
+            Comparison::GreaterOrEqual => cell >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = COMPARISONS.iter()
+            .find(|(_, comparison)| *comparison == self.comparison)
+            .map_or("", |(symbol, _)| symbol);
+        write!(f, "cell[{}] {symbol} {}", self.cell, self.value)
+    }
+}
+
+/// The source of input for the `,` command.
+/// This is `input` if it is given, and otherwise when stdin is piped or redirected its bytes are
+/// read in order, or characters are read from the terminal as they are typed, and are echoed
+/// back if `echo` is true.
+fn source(input: Option<&[u8]>, echo: bool) -> Box<dyn Read + '_> {
+    if let Some(input) = input {
+        Box::new(input)
+    } else if stdin().is_terminal() {
+        let mut term_input = TermInput::new();
+        term_input.set_echo(echo);
+        Box::new(term_input)
+    } else {
+        Box::new(stdin())
+    }
+}
+
+fn usage() {
+    println!("Brainfuck\n\
+        \n\
+        Usage:\n\
+        \n\
+        brainfuck [file | -e code | --stdin] [-v] [-h] [--version] [--eof mode]\n\
+        \x20         [--output-mode mode] [--cells n] [--cell-size n] [--grow] [--wrap-pointer]\n\
+        \x20         [--unsafe-fast] [--init hex | --init-file file] [--fill random[:seed]]\n\
+        \x20         [--input file] [--out file] [--trace] [--watch n] [--guard] [--debug]\n\
+        \x20         [--visual] [--break condition] [--test-ops] [--strict] [--auto-close]\n\
+        \x20         [--max-depth n] [-O0 | -O1 | -O2 | -O3] [--profile] [--dump]\n\
+        \x20         [--dump-window s:n] [--ascii-art] [--dump-json] [--histogram] [--quiet]\n\
+        \x20         [--from-ook] [--map chars] [--no-echo] [--normalize-newlines] [--no-bang]\n\
+        \x20         [--max-steps n] [--timeout ms] [--input-limit n] [--diff file]\n\
+        \x20         [--bench runs] [--opt-report]\n\
+        \x20         [--format | --minify | --minify-aggressive | --disasm | --decompile\n\
+        \x20         | --cfg-dot | --stats | --emit-c | --emit-rust [-o file] | --emit-bc file]\n\
+        \n\
+        Options:\n\
+        \x20 -e, --code code     run the given code rather than a file\n\
+        \x20 --stdin             read the code from stdin, the default when stdin isn't a terminal\n\
+        \x20 -h, --help          show this help\n\
+        \x20 --version           show the version\n\
+        \x20 -v, --verbose       output compile and run times, highest cell, instructions run and bytes written\n\
+        \x20 --eof mode          what `,` does at the end of input: unchanged, zero (default) or negative-one\n\
+        \x20 --output-mode mode  how `.` writes the byte: char (default), decimal or hex\n\
+        \x20 -c                  write the bytes as characters, the same as --output-mode char\n\
+        \x20 --cells n           the number of memory cells, 30000 by default\n\
+        \x20 --cell-size n       the size of each memory cell: 8 (default), 16 or 32 bits\n\
+        \x20 --grow              add memory cells when the data pointer moves past the last one\n\
+        \x20 --wrap-pointer      wrap the data pointer around from the last cell to cell 0 and back\n\
+        \x20 --unsafe-fast       skip bounds checks if the program provably stays within the cells\n\
+        \x20 --init hex          load bytes given in hexadecimal, like 0a1b, into the cells from cell 0\n\
+        \x20 --init-file file    load the bytes of a file into the cells from cell 0\n\
+        \x20 --fill random[:n]   fill the cells with pseudo-random bytes, from the seed n if given\n\
+        \x20 --input file        read the input for `,` from a file rather than stdin\n\
+        \x20 --out file          write the program's output to a file as well as stdout\n\
+        \x20 --trace             write each instruction to stderr as it is executed\n\
+        \x20 --watch n           write to stderr whenever cell n changes, can be given more than once\n\
+        \x20 --guard             show where a data pointer error happened and the instructions before it\n\
+        \x20 --debug             pause at each `#` and show the memory around the data pointer\n\
+        \x20 --visual            step through the program a key press at a time, drawing the cells\n\
+        \x20 --break condition   only pause at a `#` when a condition like cell[3]==10 holds\n\
+        \x20 --test-ops          compile `?` and `=` into assertions that the cell isn't or is 0\n\
+        \x20 --strict            reject characters that aren't commands or whitespace\n\
+        \x20 --auto-close        close any `[` left open at the end, ignore any `]` without a `[`\n\
+        \x20 --max-depth n       the most loops that can be nested inside each other, 1000 by default\n\
+        \x20 -O0 to -O3          how much to optimize: nothing, runs and `[-]`, loops (default) or unrolling\n\
+        \x20 --from-ook          read the code as Ook! rather than brainfuck\n\
+        \x20 --map chars         the characters to read as each of ><+-.,[] in turn\n\
+        \x20 --no-echo           don't show the characters typed for `,`, which --echo shows (default)\n\
+        \x20 --normalize-newlines\n\
+        \x20                     read \\r\\n and a lone \\r in the input as \\n\n\
+        \x20 --no-bang           treat `!` as a comment rather than the start of the program's input\n\
+        \x20 --profile           count the instructions executed and show a summary at the end\n\
+        \x20 --dump              show the memory cells the program used once it has finished\n\
+        \x20 --dump-window s:n   only dump the n cells starting at cell s\n\
+        \x20 --ascii-art         dump the cells as a table of hex and characters, like hexdump -C\n\
+        \x20 --histogram         show how many of the cells the program used hold each value\n\
+        \x20 --dump-json         show the final state of the program as JSON once it has finished\n\
+        \x20 --quiet             discard the program's output, and show how many bytes it wrote\n\
+        \x20 --max-steps n       stop the program with an error after it executes n instructions\n\
+        \x20 --input-limit n     read at most n bytes for `,`, and then act as if the input has run out\n\
+        \x20 --timeout ms        stop the program with an error after it runs for ms milliseconds\n\
+        \x20 --diff file         show the cells that end up different after running another program\n\
+        \x20 --bench runs        time running the program a number of times, discarding its output\n\
+        \x20 --format            write out the code with loops indented and comments removed\n\
+        \x20 --minify            write out only the commands of the code, on one line\n\
+        \x20 --minify-aggressive minify, also removing commands that cancel out like `+-` and `<>`\n\
+        \x20 --disasm            write out the compiled instructions rather than running the program\n\
+        \x20 --decompile         write out the compiled program as brainfuck code\n\
+        \x20 --cfg-dot           write out the control flow graph of the program in Graphviz DOT\n\
+        \x20 --check             only check that the program compiles, exiting with 1 if it doesn't\n\
+        \x20 --opt-report        show how many times each optimization was applied when compiling\n\
+        \x20 --stats             write out how many of each command, Ops and loops the program has\n\
+        \x20 --emit-c            write out the program translated into C rather than running it\n\
+        \x20 --emit-rust         write out the program translated into Rust rather than running it\n\
+        \x20 --emit-bc file      save the compiled program as bytecode, which can be run as a `.bfc` file\n\
+        \x20 -o file             write the translated program to a file rather than stdout\n\
+        "
+        );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_condition() {
+        let condition = Condition::parse("cell[3] == 10").unwrap();
+        assert_eq!((condition.cell, condition.value), (3, 10));
+        assert!(condition.comparison == Comparison::Equal);
+        assert!(Condition::parse("cell[0]<=2").unwrap().comparison == Comparison::LessOrEqual);
+        assert!(Condition::parse("cell[0]<2").unwrap().comparison == Comparison::Less);
+        assert!(Condition::parse("cell[x]==1").is_none());
+        assert!(Condition::parse("cell[1]=1").is_none());
+        assert!(Condition::parse("cell[1]==").is_none());
+    }
+
+    #[test]
+    fn condition_holds() {
+        let data: [u8; 4] = [0, 5, 10, 255];
+        let holds = |text| Condition::parse(text).unwrap().holds(&data);
+        assert!(holds("cell[2]==10"));
+        assert!(!holds("cell[2]!=10"));
+        assert!(holds("cell[1]<10"));
+        assert!(!holds("cell[2]<10"));
+        assert!(holds("cell[2]<=10"));
+        assert!(holds("cell[3]>254"));
+        assert!(holds("cell[1]>=5"));
+        // a cell past the end is 0
+        assert!(holds("cell[100]==0"));
+    }
+
+    fn options(args: &[&str]) -> Options {
+        let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+        match parse_args(&args) {
+            Ok(Command::Run(options)) => *options,
+            _ => panic!("{args:?} should run"),
+        }
+    }
+
+    #[test]
+    fn load_runs_on_the_current_memory() {
+        let path = std::env::temp_dir().join(format!("brainfuck_load_{}.bf", std::process::id()));
+        std::fs::write(&path, "[->+<]").unwrap();
+        let mut interpreter: Interpreter = Interpreter::new();
+        interpreter.load(&[7]);
+        load(&mut interpreter, path.to_str().unwrap(), &options(&[])).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(interpreter.data()[..2], [0, 7]);
+    }
+
+    #[test]
+    fn load_errors() {
+        let mut interpreter: Interpreter = Interpreter::new();
+        let error = load(&mut interpreter, "program.txt", &options(&[])).unwrap_err();
+        assert_eq!(error.to_string(), "Error: file program.txt was not a `.bf` file.");
+        let error = load(&mut interpreter, "no/such/file.bf", &options(&[])).unwrap_err();
+        assert!(error.to_string().starts_with("Error reading file"));
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+        match parse_args(&args) {
+            Err(e) => e,
+            Ok(_) => panic!("{args:?} should be an error"),
         }
+    }
+
+    #[test]
+    fn parse_file_and_options_in_any_order() {
+        let before = options(&["program.bf", "-v"]);
+        assert_eq!(before.file.as_deref(), Some("program.bf"));
+        assert!(before.verbose);
+        let after = options(&["--verbose", "program.bf", "--cells", "10"]);
+        assert_eq!(after.file.as_deref(), Some("program.bf"));
+        assert!(after.verbose);
+        assert_eq!(after.cells, 10);
+        assert!(options(&[]).file.is_none());
+    }
+
+    #[test]
+    fn parse_the_older_forms() {
+        // `brainfuck file.bf -v -c` chose verbose mode and characters before there were more options
+        let file = options(&["program.bf", "-c"]);
+        assert_eq!(file.file.as_deref(), Some("program.bf"));
+        assert!(file.code.is_none());
+        assert!(matches!(file.output_mode, OutputMode::Char));
+        let file = options(&["program.bf", "-v", "-c"]);
+        assert!(file.verbose && matches!(file.output_mode, OutputMode::Char));
+        assert!(matches!(options(&["program.bf", "--output-mode", "hex", "-c"]).output_mode, OutputMode::Char));
+
+        // and `brainfuck -c` alone ran the REPL
+        let repl = options(&["-c"]);
+        assert!(repl.file.is_none() && repl.code.is_none());
+        assert_eq!(options(&["-e", "+++."]).code.as_deref(), Some("+++."));
+    }
+
+    #[test]
+    fn parse_help_and_version() {
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert!(matches!(parse_args(&args(&["--help"])), Ok(Command::Help)));
+        assert!(matches!(parse_args(&args(&["program.bf", "-h"])), Ok(Command::Help)));
+        assert!(matches!(parse_args(&args(&["--version", "--unknown"])), Ok(Command::Version)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_error(&["--unknown"]), "unknown option --unknown");
+        assert_eq!(parse_error(&["--cells"]), "--cells needs a value");
+        assert_eq!(parse_error(&["--cells", "many"]), "invalid value many for --cells");
+        assert_eq!(parse_error(&["--eof", "sometimes"]), "invalid value sometimes for --eof");
+        assert_eq!(parse_error(&["--cell-size", "64"]), "invalid value 64 for --cell-size");
+        assert_eq!(parse_error(&["a.bf", "b.bf"]), "unexpected argument b.bf, as only one file can be run");
+    }
+
+    #[test]
+    fn history_is_saved_and_trimmed() {
+        let path = std::env::temp_dir().join(format!("brainfuck_history_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::open(Some(path.clone()));
+        assert!(history.lines.is_empty());
+        history.add("+++.");
+        history.add("+++.");
+        history.add("  ");
+        history.add(",.");
+        assert_eq!(History::open(Some(path.clone())).lines, ["+++.", ",."]);
+
+        let lines: Vec<String> = (0..HISTORY_SIZE + 5).map(|i| i.to_string()).collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let history = History::open(Some(path.clone()));
+        assert_eq!(history.lines, lines[5..]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), HISTORY_SIZE);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut history = History::open(None);
+        history.add("+");
+        assert_eq!(history.lines, ["+"]);
+    }
+
+    #[test]
+    fn visual_view_draws_the_cells_around_the_data_pointer() {
+        let program = brainfuck::compile("+>++.<").unwrap();
+        let mut interpreter: Interpreter = Interpreter::with_size(12);
+        let mut written = Vec::new();
+        for _ in 0..4 {
+            interpreter.step(&program, &mut &b""[..], &mut written);
+        }
+        let view = console::strip_ansi_codes(&visual_view(&interpreter, &program, &written)).to_string();
+        let lines: Vec<&str> = view.lines().collect();
+        assert_eq!(lines[0], "0004 MovePointer -1 at line 1, column 6");
+        let indices: String = (0..=9).map(|index| format!("{index:>6}")).collect();
+        assert_eq!(lines[1], indices);
+        assert_eq!(lines[2], format!("{:>6}{:>6}{}", 1, 2, "     0".repeat(8)));
+        assert_eq!(lines[3], "Output: \\u{2}");
+        assert_eq!(lines[4], "Press any key to step, or q to quit.");
+
+        interpreter.step(&program, &mut &b""[..], &mut written);
+        let view = visual_view(&interpreter, &program, &written);
+        assert!(view.starts_with("The program has finished.\n"));
+        assert!(!view.contains("Press any key"));
+    }
+
+    #[test]
+    fn hex_table_shows_hex_and_characters() {
+        let cells: Vec<u8> = b"Hi\n".iter().copied().chain([0; 15]).collect();
+        assert_eq!(
+            hex_table(&cells[14..], 14),
+            "+----------+-------------------------------------------------+------------------+\n\
+             |          | 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f | 0123456789abcdef |\n\
+             +----------+-------------------------------------------------+------------------+\n\
+             | 00000000 |                                           00 00 |               .. |\n\
+             | 00000010 | 00 00                                           | ..               |\n\
+             +----------+-------------------------------------------------+------------------+\n"
+        );
+        let table = hex_table(&cells, 0);
+        assert!(table.contains("| 00000000 | 48 69 0a 00 00 00 00 00 00 00 00 00 00 00 00 00 | Hi.............. |\n"));
 
-        true
+        let table = hex_table(&[0x41u16, 0x1234], 0);
+        assert!(table.contains(" 000f | "));
+        assert!(table.contains("| 00000000 | 0041 1234 "));
+        assert!(table.contains("| A.               |"));
     }
 }
@@ -0,0 +1,108 @@
+//! Translation from Ook!, a language with the same commands as brainfuck, each spelled as a pair of
+//! the words `Ook.`, `Ook?` and `Ook!`.
+
+use alloc::string::String;
+
+use crate::{BfError, SourcePosition};
+use crate::{DECREMENT_DP, DECREMENT_DP_VALUE, INCREMENT_DP, INCREMENT_DP_VALUE, INPUT_DP, JUMP_BACK, JUMP_FORWARD,
+    OUTPUT_DP};
+
+/// Translate Ook! code into brainfuck code, one command for each pair of words. Anything between
+/// the words is ignored, like comments in brainfuck.
+/// Returns an error if a pair of words isn't a command, or if the last word doesn't have another
+/// to pair with.
+pub fn ook_to_brainfuck(code: impl AsRef<[u8]>) -> Result<String, BfError> {
+    let code = code.as_ref();
+    let mut brainfuck = String::new();
+    let mut position = SourcePosition { line: 1, column: 1 };
+    // the punctuation and position of the first word of the pair being read
+    let mut first = None;
+
+    let mut index = 0;
+    while index < code.len() {
+        let word = match code[index..] {
+            [b'O', b'o', b'k', mark @ (b'.' | b'?' | b'!'), ..] => mark,
+            _ => {
+                position.advance(code[index]);
+                index += 1;
+                continue;
+            }
+        };
+        let start = position;
+        for &byte in &code[index..index + 4] {
+            position.advance(byte);
+        }
+        index += 4;
+
+        let Some((first_word, first_position)) = first.take() else {
+            first = Some((word, start));
+            continue;
+        };
+        let command = match (first_word, word) {
+            (b'.', b'?') => INCREMENT_DP,
+            (b'?', b'.') => DECREMENT_DP,
+            (b'.', b'.') => INCREMENT_DP_VALUE,
+            (b'!', b'!') => DECREMENT_DP_VALUE,
+            (b'!', b'.') => OUTPUT_DP,
+            (b'.', b'!') => INPUT_DP,
+            (b'!', b'?') => JUMP_FORWARD,
+            (b'?', b'!') => JUMP_BACK,
+            _ => return Err(BfError::InvalidOok { reason: "`Ook? Ook?` isn't a command", position: first_position }),
+        };
+        brainfuck.push(command);
+    }
+
+    match first {
+        Some((_, position)) => Err(BfError::InvalidOok { reason: "this word has no other to pair with", position }),
+        None => Ok(brainfuck),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use crate::{compile, Interpreter};
+
+    const HELLO_WORLD: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    /// Spell brainfuck code in Ook!, one line of 8 pairs of words at a time.
+    fn to_ook(brainfuck: &str) -> String {
+        let words: Vec<&str> = brainfuck.chars()
+            .map(|command| match command {
+                '>' => "Ook. Ook?",
+                '<' => "Ook? Ook.",
+                '+' => "Ook. Ook.",
+                '-' => "Ook! Ook!",
+                '.' => "Ook! Ook.",
+                ',' => "Ook. Ook!",
+                '[' => "Ook! Ook?",
+                ']' => "Ook? Ook!",
+                _ => unreachable!(),
+            })
+            .collect();
+        words.chunks(8).map(|line| line.join(" ")).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn hello_world() {
+        let ook = to_ook(HELLO_WORLD);
+        assert!(ook.starts_with("Ook. Ook. Ook. Ook. "));
+        let brainfuck = ook_to_brainfuck(&ook).unwrap();
+        assert_eq!(brainfuck, HELLO_WORLD);
+
+        let program = compile(&brainfuck).unwrap();
+        assert_eq!(program.ops(), compile(HELLO_WORLD).unwrap().ops());
+        let mut output = Vec::new();
+        Interpreter::<u8>::new().run(&program, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, b"Hello World!\n");
+    }
+
+    #[test]
+    fn invalid_ook() {
+        let error = ook_to_brainfuck("Ook. Ook.\nOok? Ook?").unwrap_err();
+        assert!(matches!(error, BfError::InvalidOok { position: SourcePosition { line: 2, column: 1 }, .. }));
+        let error = ook_to_brainfuck("Ook. Ook. Ook!").unwrap_err();
+        assert!(matches!(error, BfError::InvalidOok { position: SourcePosition { line: 1, column: 11 }, .. }));
+    }
+}
@@ -0,0 +1,167 @@
+//! Translation of compiled brainfuck programs into the source code of other languages, so that
+//! they can be built with that language's compiler.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::Write;
+
+use crate::{Op, Program};
+
+/// Translate a `Program` into a C program with `tape_size` memory cells.
+/// The memory cells are unsigned bytes that wrap around like the interpreter's, and `,` sets the
+/// byte at the data pointer to 0 at the end of input, like `EofMode::Zero`.
+pub fn transpile_c(program: &Program, tape_size: usize) -> String {
+    let mut c = String::new();
+    c.push_str("#include <stdio.h>\n\n");
+    c.push_str("int main(void) {\n");
+    let _ = writeln!(c, "    static unsigned char tape[{tape_size}];");
+    c.push_str("    unsigned char *p = tape;\n\n");
+
+    write_ops(&mut c, program, |op| {
+        Some(match op {
+            Op::IncrementDp => "p++;".to_string(),
+            Op::DecrementDp => "p--;".to_string(),
+            Op::IncrementDpValue => "(*p)++;".to_string(),
+            Op::DecrementDpValue => "(*p)--;".to_string(),
+            Op::OutputDp => "putchar(*p);".to_string(),
+            Op::OutputValue(value) => format!("putchar({});", value as u8),
+            Op::InputDp => "{ int c = getchar(); *p = c == EOF ? 0 : c; }".to_string(),
+            Op::JumpForward(_) => "while (*p) {".to_string(),
+            Op::JumpBackward(_) => "}".to_string(),
+            Op::AddValue(count) if count < 0 => format!("*p -= {};", -count),
+            Op::AddValue(count) => format!("*p += {count};"),
+            Op::MovePointer(count) if count < 0 => format!("p -= {};", -count),
+            Op::MovePointer(count) => format!("p += {count};"),
+            Op::SetZero => "*p = 0;".to_string(),
+            Op::MoveAdd { offset, factor } if factor < 0 => format!("p[{offset}] -= *p * {};", -factor),
+            Op::ScanZero { step } if step < 0 => format!("while (*p) p -= {};", -step),
+            Op::ScanZero { step } => format!("while (*p) p += {step};"),
+            Op::MoveAdd { offset, factor } => format!("p[{offset}] += *p * {factor};"),
+            Op::AssertNonZero => "if (!*p) { fputs(\"Assertion failed: the cell should not be 0.\\n\", stderr); return 1; }".to_string(),
+            Op::AssertZero => "if (*p) { fputs(\"Assertion failed: the cell should be 0.\\n\", stderr); return 1; }".to_string(),
+            // there is nothing to pause in a compiled program
+            Op::Breakpoint => return None,
+        })
+    });
+
+    c.push_str("\n    return 0;\n");
+    c.push_str("}\n");
+    c
+}
+
+/// Translate a `Program` into a standalone Rust program with `tape_size` memory cells.
+/// Like the C translation, `,` sets the byte at the data pointer to 0 at the end of input, and
+/// moving the data pointer out of the bounds of the memory cells panics.
+pub fn transpile_rust(program: &Program, tape_size: usize) -> String {
+    let mut rust = String::new();
+    // programs without any `,` or `>` won't need everything that's declared
+    rust.push_str("#![allow(dead_code, unused_mut)]\n\n");
+    rust.push_str("use std::io::{Read, Write};\n\n");
+    rust.push_str("fn read_byte(output: &mut impl Write) -> u8 {\n");
+    rust.push_str("    output.flush().unwrap();\n");
+    rust.push_str("    let mut byte = [0];\n");
+    rust.push_str("    match std::io::stdin().read(&mut byte).unwrap() {\n");
+    rust.push_str("        0 => 0,\n");
+    rust.push_str("        _ => byte[0],\n");
+    rust.push_str("    }\n");
+    rust.push_str("}\n\n");
+    rust.push_str("fn main() {\n");
+    let _ = writeln!(rust, "    let mut tape = [0u8; {tape_size}];");
+    rust.push_str("    let mut ptr: usize = 0;\n");
+    rust.push_str("    let mut output = std::io::stdout().lock();\n\n");
+
+    write_ops(&mut rust, program, |op| {
+        Some(match op {
+            Op::IncrementDp => "ptr += 1;".to_string(),
+            Op::DecrementDp => "ptr -= 1;".to_string(),
+            Op::IncrementDpValue => "tape[ptr] = tape[ptr].wrapping_add(1);".to_string(),
+            Op::DecrementDpValue => "tape[ptr] = tape[ptr].wrapping_sub(1);".to_string(),
+            Op::OutputDp => "output.write_all(&[tape[ptr]]).unwrap();".to_string(),
+            Op::OutputValue(value) => format!("output.write_all(&[{}]).unwrap();", value as u8),
+            Op::InputDp => "tape[ptr] = read_byte(&mut output);".to_string(),
+            Op::JumpForward(_) => "while tape[ptr] != 0 {".to_string(),
+            Op::JumpBackward(_) => "}".to_string(),
+            Op::AddValue(count) if count < 0 => format!("tape[ptr] = tape[ptr].wrapping_sub({});", -count),
+            Op::AddValue(count) => format!("tape[ptr] = tape[ptr].wrapping_add({count});"),
+            Op::MovePointer(count) if count < 0 => format!("ptr -= {};", -count),
+            Op::MovePointer(count) => format!("ptr += {count};"),
+            Op::SetZero => "tape[ptr] = 0;".to_string(),
+            Op::ScanZero { step } if step < 0 => format!("while tape[ptr] != 0 {{ ptr -= {}; }}", -step),
+            Op::ScanZero { step } => format!("while tape[ptr] != 0 {{ ptr += {step}; }}"),
+            Op::MoveAdd { offset, factor } => {
+                let cell = match offset {
+                    offset if offset < 0 => format!("tape[ptr - {}]", -offset),
+                    offset => format!("tape[ptr + {offset}]"),
+                };
+                // the factor is truncated to a byte, so that it is a valid `u8` literal
+                let method = if factor < 0 { "wrapping_sub" } else { "wrapping_add" };
+                format!("{cell} = {cell}.{method}(tape[ptr].wrapping_mul({}));", factor.unsigned_abs() as u8)
+            }
+            Op::AssertNonZero => "assert_ne!(tape[ptr], 0, \"Assertion failed: the cell should not be 0.\");".to_string(),
+            Op::AssertZero => "assert_eq!(tape[ptr], 0, \"Assertion failed: the cell should be 0.\");".to_string(),
+            Op::Breakpoint => return None,
+        })
+    });
+
+    rust.push_str("\n    output.flush().unwrap();\n");
+    rust.push_str("}\n");
+    rust
+}
+
+/// Write the statement for each Op of the program inside the body of `main`, indenting the bodies
+/// of loops. `statement` gives the statement for an Op, or `None` if the Op has no equivalent.
+fn write_ops(source: &mut String, program: &Program, statement: impl Fn(Op) -> Option<String>) {
+    let mut depth = 1;
+    for &op in program.ops() {
+        // the closing brace of a loop is indented to the level of its opening brace
+        if let Op::JumpBackward(_) = op {
+            depth -= 1;
+        }
+
+        if let Some(statement) = statement(op) {
+            let _ = writeln!(source, "{:indent$}{statement}", "", indent = depth * 4);
+        }
+
+        if let Op::JumpForward(_) = op {
+            depth += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    #[test]
+    fn c_statements() {
+        let c = transpile_c(&compile("+++.").unwrap(), 30000);
+        assert!(c.contains("static unsigned char tape[30000];"));
+        assert!(c.contains("    *p += 3;\n    putchar(*p);\n"));
+        assert!(c.contains("int main(void) {"));
+    }
+
+    #[test]
+    fn c_loops() {
+        let c = transpile_c(&compile(",[.,]").unwrap(), 16);
+        assert!(c.contains("while (*p) {"));
+        assert!(c.contains("getchar()"));
+    }
+
+    #[test]
+    fn rust_statements() {
+        let rust = transpile_rust(&compile("+++++[>+<-]>.").unwrap(), 30000);
+        assert!(rust.contains("fn main() {"));
+        assert!(rust.contains("let mut tape = [0u8; 30000];"));
+        assert!(rust.contains("tape[ptr] = tape[ptr].wrapping_add(5);"));
+        assert!(rust.contains("ptr += 1;"));
+        assert!(rust.contains("output.write_all(&[tape[ptr]]).unwrap();"));
+    }
+
+    #[test]
+    fn rust_loops() {
+        let rust = transpile_rust(&compile(",[.,]").unwrap(), 16);
+        assert!(rust.contains("while tape[ptr] != 0 {"));
+        assert!(rust.contains("tape[ptr] = read_byte(&mut output);"));
+    }
+}
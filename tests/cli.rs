@@ -0,0 +1,322 @@
+use std::process::{Command, Output, Stdio};
+
+/// Run the brainfuck binary with `args` and nothing on stdin.
+fn brainfuck(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = brainfuck(args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn max_steps_stops_endless_loops() {
+    let output = brainfuck(&["-e", "+[]", "--max-steps", "1000"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("reaching the limit of 1000 steps"));
+}
+
+fn stderr(args: &[&str]) -> String {
+    let output = brainfuck(args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn dump_shows_the_cells() {
+    assert_eq!(stderr(&["-e", "+++>++", "--dump"]), "\nData pointer: 1\n       0:   3\n       1:   2\n");
+}
+
+#[test]
+fn disasm_lists_the_ops() {
+    let listing = stdout(&["-e", "[>.<]", "--disasm"]);
+    let lines: Vec<&str> = listing.lines().map(str::trim_end).collect();
+    assert_eq!(lines, [
+        "0000 JumpForward -> 4                 line 1, column 1",
+        "0001 MovePointer 1                    line 1, column 2",
+        "0002 OutputDp                         line 1, column 3",
+        "0003 MovePointer -1                   line 1, column 4",
+        "0004 JumpBackward -> 0                line 1, column 5",
+    ]);
+}
+
+/// Write `contents` to a file called `name` in a directory for this test run, returning its path.
+fn temp_file(name: &str, contents: &[u8]) -> String {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn files_with_invalid_utf8() {
+    let path = temp_file("invalid_utf8.bf", b"\xff\xfe comment \xc3(\n++++++++[>++++++++<-]>+.\x80");
+    assert_eq!(stdout(&[&path]), "A");
+}
+
+#[test]
+fn no_endless_loop_warning_for_comment_loops() {
+    let output = brainfuck(&["examples/hello_world.bf"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = brainfuck(&["-e", "+[]", "--max-steps", "10"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("never ends once it is entered"));
+}
+
+#[test]
+fn endless_loops_are_only_warned_about_from_zeroed_cells() {
+    let warned = |args: &[&str]| String::from_utf8_lossy(&brainfuck(args).stderr).contains("never ends");
+    assert!(warned(&["-e", "+[>+<]", "--max-steps", "10"]));
+    assert!(!warned(&["-e", "[>+<]", "--init", "01", "--max-steps", "10"]));
+    assert!(!warned(&["-e", "+[>+<]", "--fill", "random:1", "--max-steps", "10"]));
+    // with a single wrapping cell, `>` comes back to the same cell, so the loop does end
+    assert!(!warned(&["-e", "+[>+<]", "--cells", "1", "--wrap-pointer"]));
+    // strict mode doesn't turn the warning into an error, so the program still runs
+    let output = brainfuck(&["--strict", "-e", "+[]", "--max-steps", "10"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("never ends once it is entered"));
+    assert!(stderr.contains("reaching the limit of 10 steps"));
+}
+
+#[test]
+fn bang_gives_the_input() {
+    assert_eq!(stdout(&["-e", ",[.,]!hello"]), "hello");
+    // only the first `!` ends the code
+    assert_eq!(stdout(&["-e", ",[.,]!hi!"]), "hi!");
+    assert_eq!(stdout(&["examples/hello_world.bf"]), "Hello World!\n");
+}
+
+#[test]
+fn no_bang_makes_it_a_comment() {
+    assert_eq!(stdout(&["--no-bang", "-e", ",[.,]!hello"]), "");
+    assert_eq!(stdout(&["--no-bang", "-e", "[Hi!]++++++++[>++++++++<-]>+."]), "A");
+}
+
+#[test]
+fn bench_reports_the_runs() {
+    let report = stdout(&["-e", ",[.,]!abc", "--bench", "5"]);
+    let rows: Vec<&str> = report.lines().map(|line| line.split_whitespace().next().unwrap_or("")).collect();
+    assert_eq!(rows, ["Runs", "Min", "Median", "Mean", "Max"]);
+    assert!(report.starts_with("Runs    5\n"));
+}
+
+#[test]
+fn cell_size_picks_the_width() {
+    let args = |size| ["--cell-size", size, "--output-mode", "decimal", "-e", "-."];
+    assert_eq!(stdout(&args("8")), "255 ");
+    assert_eq!(stdout(&args("16")), "65535 ");
+    assert_eq!(stdout(&args("32")), "4294967295 ");
+}
+
+#[test]
+fn watch_reports_changes_to_the_cell() {
+    assert_eq!(stderr(&["-e", ">>>>>+", "--watch", "5"]), "0001 watch cell 5: 0 -> 1\n");
+    assert_eq!(stderr(&["-e", ">>>>>+>+", "--watch", "3"]), "");
+}
+
+#[test]
+fn init_loads_the_cells() {
+    assert_eq!(brainfuck(&["-e", ">.", "--init", "010203"]).stdout, [2]);
+    let output = brainfuck(&["-e", ">.", "--init", "01020304", "--cells", "3"]);
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("4 bytes to load but only 3 memory cells"));
+}
+
+#[test]
+fn quiet_counts_the_output() {
+    let output = brainfuck(&["-e", "++++++++[>++++++++<-]>+.+.", "--quiet"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Output bytes: 2\n");
+}
+
+#[test]
+fn dump_json() {
+    let json = stdout(&["-e", "+++>++>+<", "--dump-json"]);
+    assert_eq!(json, "{ \"data_pointer\": 1, \"instruction_count\": 6, \"tape\": [3, 2, 1] }\n");
+    assert!(brainfuck(&["-e", "+++>++>+<", "--dump-json"]).stderr.is_empty());
+
+    // the program's output comes first, and with `--quiet` only the JSON is on stdout
+    assert!(stdout(&["-e", "+++.", "--dump-json"]).starts_with("\x03{ "));
+    let quiet = stdout(&["-e", "+++.", "--dump-json", "--quiet"]);
+    assert!(quiet.starts_with("{ ") && quiet.ends_with("}\n"));
+
+    // each field is a key and a value, with the tape as an array of numbers
+    let fields = json.trim().strip_prefix('{').and_then(|json| json.strip_suffix('}')).unwrap();
+    let (head, tape) = fields.split_once(r#""tape": "#).unwrap();
+    assert!(tape.trim().starts_with('[') && tape.trim().ends_with(']'));
+    let data_pointer = head.split(',').find_map(|field| field.trim().strip_prefix(r#""data_pointer": "#));
+    assert_eq!(data_pointer.map(str::parse::<usize>), Some(Ok(1)));
+}
+
+#[test]
+fn input_from_a_file() {
+    let contents = b"some input\nover two lines\n";
+    let path = temp_file("input.txt", contents);
+    assert_eq!(brainfuck(&["-e", ",[.,]", "--input", &path]).stdout, contents);
+    // once the file runs out, `,` does whatever --eof says
+    let empty = temp_file("empty_input.txt", b"");
+    assert_eq!(brainfuck(&["-e", "+++++,.", "--input", &empty, "--eof", "unchanged"]).stdout, [5]);
+}
+
+#[test]
+fn out_copies_the_output_to_a_file() {
+    let path = temp_file("out.txt", b"");
+    assert_eq!(stdout(&["-e", "++++++++[>++++++++<-]>+.+.", "--out", &path]), "AB");
+    assert_eq!(std::fs::read(&path).unwrap(), b"AB");
+}
+
+#[test]
+fn histogram_counts_cell_values() {
+    assert_eq!(stderr(&["-e", "+>++>+++", "--histogram"]), "\nCell values:\n       1: 1\n       2: 1\n       3: 1\n");
+    assert_eq!(
+        stderr(&["-e", "+>++>+++>>+<<<<", "--histogram"]),
+        "\nCell values:\n       0: 1\n       1: 2\n       2: 1\n       3: 1\n"
+    );
+}
+
+#[test]
+fn dump_window_limits_the_cells() {
+    assert_eq!(
+        stderr(&["-e", "+>++>+++>++++>+++++>++++++", "--dump-window", "2:3"]),
+        "\nData pointer: 5\n       2:   3\n       3:   4\n       4:   5\n"
+    );
+    let output = brainfuck(&["-e", "+", "--dump-window", "29999:3"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("past the end of the 30000 memory cells"));
+}
+
+#[test]
+fn programs_without_commands_do_nothing() {
+    for code in ["", " \n\t ", "just a comment", "[+-.,<>] a comment loop"] {
+        let output = brainfuck(&["-e", code]);
+        assert!(output.status.success(), "{code:?}");
+        assert!(output.stdout.is_empty(), "{code:?}");
+        assert!(output.stderr.is_empty(), "{code:?}: {}", String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+#[test]
+fn older_arguments_still_work() {
+    assert_eq!(stdout(&["examples/hello_world.bf", "-c"]), "Hello World!\n");
+    assert_eq!(stdout(&["examples/hello_world.bf", "-v", "-c"]).lines().nth(1), Some("Hello World!"));
+}
+
+#[test]
+fn stats_count_loops_and_nesting() {
+    for level in ["-O0", "-O3"] {
+        let stats = stdout(&["--stats", level, "-e", "[[+]]"]);
+        assert!(stats.contains("Loops       2\n"), "{level}: {stats}");
+        assert!(stats.contains("Max depth   2\n"), "{level}: {stats}");
+    }
+    assert!(stdout(&["--stats", "-e", "+>[-]"]).contains("Max depth   1\n"));
+}
+
+#[test]
+fn map_remaps_the_commands() {
+    assert_eq!(stdout(&["--map", "RLIDOGnx", "-e", "IIIIIIn RIIIIIIIIIILDx RO"]), "<");
+    assert!(stderr(&["--map", "RLIDOGn", "-e", "+"]).contains("needs 8 different characters"));
+}
+
+#[test]
+fn check_only_compiles() {
+    let output = brainfuck(&["--check", "-e", "["]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unmatched '['"));
+    let output = brainfuck(&["--check", "-e", "[]"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+    // the program isn't run, so it doesn't write anything
+    assert_eq!(stdout(&["--check", "-e", "+++."]), "");
+}
+
+#[test]
+fn includes_splice_in_other_files() {
+    temp_file("include_a.bf", b"++++++++[>++++++++<-]>+\n");
+    let path = temp_file("include_main.bf", b";include \"include_a.bf\"\n.+.\n");
+    assert_eq!(stdout(&[&path]), "AB");
+
+    let path = temp_file("include_self.bf", b"+\n;include \"include_self.bf\"\n.\n");
+    assert!(stderr(&[&path]).contains("is included from within itself"));
+    temp_file("include_loop_a.bf", b";include \"include_loop_b.bf\"\n");
+    let path = temp_file("include_loop_b.bf", b";include \"include_loop_a.bf\"\n");
+    assert!(stderr(&[&path]).contains("is included from within itself"));
+}
+
+#[test]
+fn piped_input_is_never_echoed() {
+    use std::io::Write;
+
+    for echo in ["--echo", "--no-echo"] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+            .args([echo, "-e", ",+.,+."])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"ab").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.stdout, b"bc", "{echo}");
+        assert!(output.stderr.is_empty(), "{echo}");
+    }
+}
+
+#[test]
+fn diff_shows_the_cells_that_differ() {
+    let path = temp_file("diff_other.bf", b"++>+++>>+");
+    assert_eq!(stdout(&["-e", "+-++>+++>>+", "--diff", &path]), "The memory cells are the same.\n");
+    assert_eq!(stdout(&["-e", "+>+++", "--diff", &path]), "       0:   1   2\n       3:   0   1\n");
+}
+
+#[test]
+fn fill_is_the_same_for_the_same_seed() {
+    let fill = |seed| stderr(&["--fill", seed, "--cells", "16", "--dump", "--dump-window", "0:16", "-e", ""]);
+    let first = fill("random:42");
+    assert_eq!(first, fill("random:42"));
+    assert_ne!(first, fill("random:43"));
+    assert_ne!(first, stderr(&["--cells", "16", "--dump", "--dump-window", "0:16", "-e", ""]));
+}
+
+#[test]
+fn auto_close_runs_unbalanced_code() {
+    assert_eq!(
+        stdout(&["--auto-close", "--disasm", "-O0", "-e", "[+"]),
+        stdout(&["--disasm", "-O0", "-e", "[+]"])
+    );
+    let output = brainfuck(&["--auto-close", "-e", "[+"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Warning: the '[' at line 1, column 1 is unmatched, so a ']' is added at the end of the code.\n"
+    );
+    let output = brainfuck(&["--auto-close", "-e", "+++[-.]]"]);
+    assert_eq!(output.stdout, [2, 1, 0]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the ']' at line 1, column 8 is unmatched, so it is removed"));
+    assert!(stderr(&["-e", "[+"]).contains("Unmatched '['"));
+}
+
+#[test]
+fn verbose_times_compiling_and_running() {
+    let output = stdout(&["--verbose", "--quiet", "-e", "+++."]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].starts_with("Compilation succeeded in "), "{output}");
+    assert!(lines.iter().any(|line| line.starts_with("Execution finished in ")), "{output}");
+    assert!(!output.contains('\u{3}'), "{output}");
+    assert!(!stdout(&["--quiet", "-e", "+++."]).contains("Execution finished"));
+}
+
+#[test]
+fn output_before_an_error_is_kept() {
+    let code = "++++++++[>++++++++++++<-]>+.+.<<<";
+    let path = temp_file("error_out.txt", b"");
+    for args in [&["-e", code][..], &["--out", &path, "-e", code]] {
+        let output = brainfuck(args);
+        assert_eq!(output.stdout, b"ab");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "Cannot decrement data pointer below 0.\n");
+    }
+    assert_eq!(std::fs::read(&path).unwrap(), b"ab");
+}
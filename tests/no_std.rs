@@ -0,0 +1,28 @@
+use std::process::Command;
+
+/// Run cargo on this crate without the default features, with a target directory of its own so
+/// that it doesn't wait on the build running these tests.
+fn cargo_without_std(args: &[&str]) {
+    let output = Command::new(env!("CARGO"))
+        .args(args)
+        .args(["--lib", "--no-default-features", "--offline", "--quiet"])
+        .arg("--manifest-path")
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+        .arg("--target-dir")
+        .arg(concat!(env!("CARGO_TARGET_TMPDIR"), "/no_std"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// The core of the crate has to build without `std`, for running on devices that don't have it.
+#[test]
+fn builds_without_std() {
+    cargo_without_std(&["build"]);
+}
+
+/// The tests of the core that don't need `std` have to pass without it too.
+#[test]
+fn tests_pass_without_std() {
+    cargo_without_std(&["test"]);
+}